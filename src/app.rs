@@ -3,28 +3,145 @@ use crate::{tui, event, config, stream::StreamManager, stream::ServerId, stream:
 use ratatui::DefaultTerminal;
 use ratatui::widgets::ListState;
 use ratatui::text::Span;
+use ratatui::layout::Rect;
 use crossterm::terminal::{self};
 use tokio::sync::mpsc;
 use std::io::Result;
 use textwrap::{wrap, Options};
-use regex::Regex;
+use crate::message::IrcMessage;
+use crate::logger::{Backlog, Logger};
+use crate::search::Search;
+use crate::complete::Completion;
+use crate::ctcp::{self, Ctcp};
+use crate::mode::{self, User, ModeTable, ModeChange};
+use crate::isupport::{self, ISupport};
+use crate::casemap::{CaseMap, CaseMapping};
+use crate::whois::Whois;
+use crate::userlist::UserPopup;
+use crate::chanlist::{self, ChannelList};
+use crate::layout::{self, WindowLayout};
+use crate::textstyle;
 use std::collections::BTreeMap;
-use std::collections::btree_map::Entry;
+use chrono::{DateTime, Local};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Message,
+    // Inserted when the date changes between two lines
+    DayChange,
+    // Read back from the log when the buffer was opened
+    Backlog,
+    // Separator between the backlog and live traffic
+    BacklogEnd,
+    // CTCP ACTION, /me
+    Action,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    pub time: DateTime<Local>,
+    pub nick: String,
+    pub text: String,
+    pub kind: LineKind,
+}
+
+impl ChatLine {
+    pub fn new(nick: &str, text: &str) -> ChatLine {
+        ChatLine::at(Local::now(), nick, text)
+    }
+
+    pub fn at(time: DateTime<Local>, nick: &str, text: &str) -> ChatLine {
+        ChatLine { time, nick: nick.to_string(), text: text.to_string(), kind: LineKind::Message }
+    }
+}
+
+// Set with 332 and TOPIC, setter and time come from 333
+#[derive(Debug, Clone, Default)]
+pub struct Topic {
+    pub text: String,
+    pub setter: Option<String>,
+    pub time: Option<DateTime<Local>>,
+}
+
+// What happened in a buffer since it was last looked at, the highest level is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Activity {
+    #[default]
+    None,
+    // Joins, parts, modes and server replies
+    Events,
+    Messages,
+    // Own nick mentioned, or a private message
+    Highlight,
+}
 
 #[derive(Default)]
 pub struct ChannelData {
-    pub chat_list: Vec<(String, String)>,
-    pub user_list: Vec<String>,
+    pub chat_list: Vec<ChatLine>,
+    pub user_list: Vec<User>,
     pub chat_pos: usize,
-    pub notification: bool,
+    pub activity: Activity,
+    // Messages since the buffer was last active
+    pub unread: usize,
+    // Channel modes with their argument, key and limit
+    pub modes: BTreeMap<char, Option<String>>,
+    // Kicked, the buffer stays open until it is joined again or closed
+    pub parted: bool,
+    pub topic: Option<Topic>,
 }
 
-type ChannelName = String;
+impl ChannelData {
+    fn mark(&mut self, level: Activity) {
+        self.activity = self.activity.max(level);
+        if level >= Activity::Messages {
+            self.unread += 1;
+        }
+    }
+
+    pub fn mark_read(&mut self) {
+        self.activity = Activity::None;
+        self.unread = 0;
+    }
+}
+
+// Channel and query buffers, looked up by the server's casemapping
+pub type ChannelMap = CaseMap<ChannelData>;
 
 #[derive(Default)]
 pub struct ServerData {
-    pub channels: BTreeMap<ChannelName, ChannelData>,
+    pub channels: ChannelMap,
     pub nick: String,
+    pub caps: Vec<String>,
+    pub mode_table: ModeTable,
+    pub isupport: ISupport,
+    // Marked away with /away, from 306 and 305
+    pub away: bool,
+}
+
+impl ServerData {
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.iter().any(|c| c == cap)
+    }
+
+    pub fn casemap(&self) -> CaseMapping {
+        self.isupport.casemapping
+    }
+
+    pub fn is_own_nick(&self, nick: &str) -> bool {
+        self.casemap().eq(&self.nick, nick)
+    }
+
+    // Own nick as a word of the text, "nick:" and "@nick" count, "nickname" does not
+    pub fn mentions(&self, text: &str) -> bool {
+        let casemap = self.casemap();
+        text.split(|c: char| !(c.is_alphanumeric() || "[]\\`_^{|}-".contains(c)))
+            .any(|word| !word.is_empty() && casemap.eq(word, &self.nick))
+    }
+
+    // NETWORK from ISUPPORT, the address we connected to until it is known
+    pub fn display_name<'a>(&'a self, server_id: &'a str) -> &'a str {
+        self.isupport.network.as_deref().unwrap_or(server_id)
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -36,6 +153,8 @@ pub enum Popup {
     Help,
     User,
     Channel,
+    Search,
+    Whois,
 }
 
 #[derive(Default)]
@@ -56,19 +175,46 @@ pub struct App {
     pub color_state_fg: ListState,
     pub color_state_bg: ListState,
     pub channel_state: ListState,
-    pub list_response: Vec<String>,
+    pub chanlist: ChannelList,
     pub character_index: usize,
     pub prompt_list: Vec<String>,
     pub prompt_pos: usize,
-    pub list_pos: usize,
-    pub menu_pos: usize,
-    pub split: (bool, String, String, String, String),
+    pub layout: WindowLayout,
     pub style_bg: (u8, u8, u8),
     pub style_fg: (u8, u8, u8),
     pub style_notif: (u8, u8, u8),
     pub style_highlight: (u8, u8, u8),
     pub style_txt: (u8, u8, u8),
     pub input_mode: Vec<Span<'static>>,
+    pub timestamp_format: String,
+    // Time of the net line being handled, from the server-time tag if negotiated
+    pub msg_time: Option<DateTime<Local>>,
+    pub logger: Logger,
+    // Where the logger sends the backlog of a buffer that was just opened
+    pub backlog_tx: Option<mpsc::UnboundedSender<Backlog>>,
+    pub search: Search,
+    pub completion: Option<Completion>,
+    // name without '/' -> commands it runs
+    pub aliases: BTreeMap<String, Vec<String>>,
+    pub ctcp: Ctcp,
+    pub rejoin_on_kick: bool,
+    // Last invite as (server, channel), joined with Alt+i
+    pub invite: Option<(ServerId, String)>,
+    pub whois: Whois,
+    pub user_popup: UserPopup,
+    // Nicks whose messages, notices and invites are dropped
+    pub ignores: Vec<String>,
+    // Buffer tree on the left, toggled with F4
+    pub sidebar: bool,
+    pub sidebar_width: u16,
+    // Where the sidebar was drawn and the buffer number on each of its rows, for mouse clicks
+    pub sidebar_area: Rect,
+    pub sidebar_rows: Vec<Option<usize>>,
+    // Users of the active channel on the right, toggled with F5
+    pub nicklist: bool,
+    pub nicklist_width: u16,
+    // Terminal width below which the nicklist is left out
+    pub nicklist_min_width: u16,
 }
 
 impl App {
@@ -76,6 +222,8 @@ impl App {
         
         let (input_tx, mut input_rx) = tokio::sync::mpsc::unbounded_channel();
         let (net_tx, mut net_rx) = mpsc::unbounded_channel::<(ServerId, NetEvent)>();
+        let (backlog_tx, mut backlog_rx) = mpsc::unbounded_channel::<Backlog>();
+        self.backlog_tx = Some(backlog_tx);
 
         self.active_nick = whoami::username();
         self.real = whoami::realname();
@@ -86,6 +234,12 @@ impl App {
         self.style_notif = (140, 255, 238);
         self.style_highlight = (238, 140, 255);
         self.style_txt = (255, 255, 255);
+        self.timestamp_format = "[%H:%M]".to_string();
+        self.sidebar = true;
+        self.sidebar_width = 20;
+        self.nicklist = true;
+        self.nicklist_width = 18;
+        self.nicklist_min_width = 100;
 
         let system_server = self.server_list
             .entry("System".to_string())
@@ -94,15 +248,13 @@ impl App {
         system_server.channels
             .insert("Status".to_string(), ChannelData {
             chat_list: vec![
-                (String::from("System"),"    __           _           ___ _           _   ".to_string()),
-                (String::from("System"),"   /__\\_   _ ___| |_ _   _  / __\\ |__   __ _| |_ ".to_string()),
-                (String::from("System"),"  / \\// | | / __| __| | | |/ /  | '_ \\ / _` | __|".to_string()), 
-                (String::from("System")," / _  \\ |_| \\__ \\ |_| |_| / /___| | | | (_| | |_ ".to_string()), 
-                (String::from("System")," \\/ \\_/\\__,_|___/\\__|\\__, \\____/|_| |_|\\__,_|\\__|".to_string()), 
-                (String::from("System"),"                     |___/                       ".to_string())],
-            user_list: Vec::new(),
-            chat_pos: 0,
-            notification: false,
+                ChatLine::new("System", "    __           _           ___ _           _   "),
+                ChatLine::new("System", "   /__\\_   _ ___| |_ _   _  / __\\ |__   __ _| |_ "),
+                ChatLine::new("System", "  / \\// | | / __| __| | | |/ /  | '_ \\ / _` | __|"),
+                ChatLine::new("System", " / _  \\ |_| \\__ \\ |_| |_| / /___| | | | (_| | |_ "),
+                ChatLine::new("System", " \\/ \\_/\\__,_|___/\\__|\\__, \\____/|_| |_|\\__,_|\\__|"),
+                ChatLine::new("System", "                     |___/                       ")],
+            ..Default::default()
         });
         system_server.nick = self.active_nick.clone();
 
@@ -116,6 +268,12 @@ impl App {
 
         config::read_theme(self);
         config::read_config(self);
+        config::read_logging(self);
+        config::read_aliases(self);
+        config::read_ctcp(self);
+        if let Some(saved) = layout::load() {
+            self.layout = saved;
+        }
         //config::read_autojoin(self, &net_tx).await;
 
         // Spawn input handler
@@ -132,6 +290,9 @@ impl App {
                 Some((sid, netmsg)) = net_rx.recv() => {
                     self.handle_net_msg(sid, netmsg);
                 }
+                Some(backlog) = backlog_rx.recv() => {
+                    self.backlog_loaded(backlog);
+                }
                 else => {
                     break;
                 }
@@ -147,230 +308,607 @@ impl App {
                 let bytes = line.clone().into_bytes();
                 self.spark_data = bytes.iter().map(|&b| b as u64).collect();
 
-                let mut value = line.split_whitespace();
-                let prefix = value.next();
-                let arg = value.next();
-                let command_pos = line.find(arg.unwrap_or(""));
-                let command = &line[command_pos.unwrap_or(0) as usize + arg.unwrap_or("").len()..];
-
-                match prefix {
-                    Some("PING") => {
-                        let pong_line = line.to_string().replace("PING", "PONG");
-                        self.stream_mgr.send_line(server_id.clone(), pong_line);
-                    },
-                    _ => {}
+                let msg = match IrcMessage::parse(&line) {
+                    Some(m) => m,
+                    None => return,
+                };
+                let source = msg.source.as_ref().map(|s| s.to_string()).unwrap_or_default();
+                self.msg_time = None;
+                if self.server_list.get(&server_id).is_some_and(|s| s.has_cap("server-time")) {
+                    if let Some(time) = msg.tag("time").and_then(|t| DateTime::parse_from_rfc3339(t).ok()) {
+                        self.msg_time = Some(time.with_timezone(&Local));
+                    }
                 }
-                match arg {
-                    Some("001") => {
-                        //Welcome, parse autojoin channels
+
+                let ignored = matches!(msg.command.as_str(), "PRIVMSG" | "NOTICE" | "INVITE") && self.is_ignored(&server_id, msg.nick());
+                match msg.command.as_str() {
+                    _ if ignored => {}
+                    "PING" => {
+                        let pong = IrcMessage { command: "PONG".to_string(), params: msg.params.clone(), ..Default::default() };
+                        self.stream_mgr.send_priority(server_id.clone(), pong.to_string());
+                    }
+                    "001" => {
+                        //Welcome, the first param is the nick the server registered us with, which may differ after 433
+                        if let Some(server) = self.server_list.get_mut(&server_id) {
+                            server.nick = msg.param(0).to_string();
+                            if self.active_server == server_id {
+                                self.active_nick = server.nick.clone();
+                            }
+                            //Rejoin channels still open from before a reconnect, with their key, parted ones stay parted
+                            for (channel_name, channel_data) in server.channels.iter_mut() {
+                                if server.isupport.is_channel(channel_name) && !channel_data.parted {
+                                    channel_data.user_list.clear();
+                                    let join = match channel_data.modes.get(&'k').cloned().flatten() {
+                                        Some(key) => IrcMessage::new("JOIN", &[channel_name, &key]),
+                                        None => IrcMessage::new("JOIN", &[channel_name]),
+                                    };
+                                    self.stream_mgr.send_line(server_id.clone(), join.to_string());
+                                }
+                            }
+                        }
+                        //parse autojoin channels
                         config::autojoin_channel(self, server_id);
                     }
-                    Some("NOTICE") => {
-                        if prefix.unwrap_or("").to_uppercase().starts_with(":ALIS!") {
-                            let start_byte = line.find("#");
-                            let result = &line[start_byte.unwrap_or(0)..];
-                            self.list_pos = 0;
-                            self.popup = Popup::List;
-                            if result.to_uppercase().contains("RETURNING MAXIMUM OF") {
-                                //Do not push
-                            } else if result.to_uppercase().contains("MAXIMUM CHANNEL OUTPUT REACHED"){
-                                //Do not push
-                            } else {
-                                self.list_response.push(result.to_string());
+                    "NOTICE" => {
+                        if let Some((verb, args)) = ctcp::parse(msg.trailing()) {
+                            //Answer to a /ctcp request
+                            let text = match ctcp::ping_lag(&args) {
+                                Some(lag) if verb == "PING" => format!("CTCP PING reply from {}: {:.3}s", msg.nick(), lag),
+                                _ => format!("CTCP {} reply from {}: {}", verb, msg.nick(), args),
+                            };
+                            self.chat_bounds(text, server_id.clone(), "Status".to_string(), "CTCP".to_string());
+                        } else if msg.nick().eq_ignore_ascii_case("ALIS") && chanlist::alis_notice(self, &server_id, msg.trailing()) {
+                            //Part of an /alis list, it went into the popup
+                        } else {
+                            self.chat_bounds(format!("-{}- {}", msg.nick(), msg.trailing()), server_id.clone(), "Status".to_string(), msg.command.clone());
+                        }
+                    }
+                    "005" => {
+                        //ISUPPORT: own nick, tokens, trailing text
+                        if let Some(server) = self.server_list.get_mut(&server_id) {
+                            let end = msg.params.len().saturating_sub(1);
+                            isupport::parse(server, msg.params.get(1..end).unwrap_or(&[]));
+                        }
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
+                    }
+                    "324" => {
+                        //Channel modes: own nick, channel, modes, args
+                        let chan = msg.param(1).to_string();
+                        if let Some(channel) = self.server_list.get_mut(&server_id).and_then(|s| s.channels.get_mut(&chan)) {
+                            channel.modes.clear();
+                        }
+                        let changes = self.server_list.get(&server_id).map(|s| s.mode_table.parse_changes(msg.params.get(2..).unwrap_or(&[]))).unwrap_or_default();
+                        self.apply_channel_modes(&server_id, &chan, &changes);
+                    }
+                    "MODE" => {
+                        let target = msg.param(0).to_string();
+                        if self.is_channel(&server_id, &target) && msg.params.len() > 1 {
+                            let changes = self.server_list.get(&server_id).map(|s| s.mode_table.parse_changes(&msg.params[1..])).unwrap_or_default();
+                            self.apply_channel_modes(&server_id, &target, &changes);
+                            let text = format!("{} sets mode {}", msg.nick(), msg.params[1..].join(" "));
+                            self.chat_bounds(text, server_id.clone(), target, msg.command.clone());
+                        } else {
+                            self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
+                        }
+                    }
+                    "KICK" => {
+                        //channel, victim, reason
+                        let chan = msg.param(0).to_string();
+                        let victim = msg.param(1);
+                        let reason = msg.param(2);
+                        let mut rejoin_key = None;
+                        let mut kicked = false;
+                        if let Some(server) = self.server_list.get_mut(&server_id) {
+                            let casemap = server.casemap();
+                            kicked = server.is_own_nick(victim);
+                            if let Some(channel) = server.channels.get_mut(&chan) {
+                                if kicked {
+                                    channel.user_list.clear();
+                                    channel.parted = true;
+                                    rejoin_key = Some(channel.modes.get(&'k').cloned().flatten());
+                                } else {
+                                    channel.user_list.retain(|u| !casemap.eq(&u.nick, victim));
+                                }
                             }
+                        }
+                        let text = if kicked {
+                            format!("You were kicked from {} by {} ({})", chan, msg.nick(), reason)
                         } else {
-                            self.chat_bounds(line.to_string(), server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                            format!("{} was kicked by {} ({})", victim, msg.nick(), reason)
+                        };
+                        self.chat_bounds(text, server_id.clone(), chan.clone(), msg.command.clone());
+                        if let (true, Some(key)) = (self.rejoin_on_kick, rejoin_key) {
+                            let join = match &key {
+                                Some(k) => IrcMessage::new("JOIN", &[&chan, k]),
+                                None => IrcMessage::new("JOIN", &[&chan]),
+                            };
+                            self.stream_mgr.send_line(server_id.clone(), join.to_string());
                         }
                     }
-                    Some("366") => {
+                    "TOPIC" => {
+                        let chan = msg.param(0).to_string();
+                        let topic = if msg.param(1).is_empty() {
+                            None
+                        } else {
+                            Some(Topic { text: msg.param(1).to_string(), setter: Some(msg.nick().to_string()), time: Some(self.msg_time.unwrap_or_else(Local::now)) })
+                        };
+                        if let Some(channel) = self.server_list.get_mut(&server_id).and_then(|s| s.channels.get_mut(&chan)) {
+                            channel.topic = topic;
+                        }
+                        let text = format!("{} changed the topic to: {}", msg.nick(), msg.param(1));
+                        self.chat_bounds(text, server_id.clone(), chan, msg.command.clone());
+                    }
+                    "INVITE" => {
+                        //own nick, channel
+                        let chan = msg.param(1).to_string();
+                        let text = format!("{} invites you to {}, Alt+i to join", msg.nick(), chan);
+                        self.invite = Some((server_id.clone(), chan));
+                        self.chat_bounds(text.clone(), server_id.clone(), "Status".to_string(), msg.command.clone());
+                        if self.active_server == server_id && self.active_channel != "Status" {
+                            self.chat_bounds(text, server_id.clone(), self.active_channel.clone(), msg.command.clone());
+                        }
+                    }
+                    "311" | "312" | "314" | "317" | "319" | "330" => {
+                        //WHOIS and WHOWAS replies, shown together at the end
+                        let casemap = self.server_list.get(&server_id).map(|s| s.casemap()).unwrap_or_default();
+                        self.whois.reply(&server_id, casemap, &msg);
+                    }
+                    "318" | "369" => {
+                        let casemap = self.server_list.get(&server_id).map(|s| s.casemap()).unwrap_or_default();
+                        if self.whois.finish(&server_id, casemap, msg.param(1)) {
+                            self.popup = Popup::Whois;
+                        }
+                    }
+                    "301" => {
+                        //RPL_AWAY: own nick, nick, message. Part of a whois, or shown in the query if one is open, else in the server Status
+                        let nick = msg.param(1).to_string();
+                        let casemap = self.server_list.get(&server_id).map(|s| s.casemap()).unwrap_or_default();
+                        if !self.whois.away(&server_id, casemap, &nick, msg.param(2)) {
+                            let has_query = self.server_list.get(&server_id).is_some_and(|s| s.channels.contains_key(&nick));
+                            let buffer = if has_query { nick.clone() } else { "Status".to_string() };
+                            self.chat_bounds(format!("{} is away: {}", nick, msg.param(2)), server_id.clone(), buffer, msg.command.clone());
+                        }
+                    }
+                    "305" | "306" => {
+                        //No longer / now marked as away
+                        if let Some(server) = self.server_list.get_mut(&server_id) {
+                            server.away = msg.command == "306";
+                        }
+                        self.chat_bounds(msg.param(1).to_string(), server_id.clone(), "Status".to_string(), msg.command.clone());
+                    }
+                    "366" => {
                         //Hide Incomming Message
                     }
-                    Some("322") => {
+                    "321" => {
+                        //HANDLE LIST start
+                        chanlist::list_start(self, &server_id);
+                    }
+                    "322" => {
                         //HANDLE LIST COMMAND
-                        self.list_pos = 0;
-                        self.popup = Popup::List;
-                        let start_byte = line.find('#');
-                        let result = &line[start_byte.unwrap_or(0)..];
-                        self.list_response.push(result.to_string());
-                    }
-                    Some("331") => {
-                        //HANDLE no topic
-                        let start_byte = command.find('#');
-                        let end_byte = command.find(':');
-                        self.chat_bounds(command[end_byte.unwrap_or(0)+1..].to_string(), server_id.clone(), command[start_byte.unwrap_or(0)..end_byte.unwrap_or(0)-1].to_owned(), arg.unwrap_or("").to_string());
-                    }
-                    Some("332") => {
-                        //HANDLE topic
-                        let start_byte = command.find('#');
-                        let end_byte = command.find(':');
-                        self.chat_bounds(command[end_byte.unwrap_or(0)+1..].to_string(), server_id.clone(), command[start_byte.unwrap_or(0)..end_byte.unwrap_or(0)-1].to_owned(), arg.unwrap_or("").to_string());
-                    }
-                    Some("433") => {
+                        chanlist::reply(self, &server_id, &msg);
+                    }
+                    "323" => {
+                        //HANDLE LIST end
+                        chanlist::list_end(self);
+                    }
+                    "331" | "332" => {
+                        //HANDLE topic / no topic
+                        let topic = if msg.command == "332" { Some(Topic { text: msg.param(2).to_string(), ..Default::default() }) } else { None };
+                        if let Some(channel) = self.server_list.get_mut(&server_id).and_then(|s| s.channels.get_mut(msg.param(1))) {
+                            channel.topic = topic;
+                        }
+                        self.chat_bounds(msg.param(2).to_string(), server_id.clone(), msg.param(1).to_string(), msg.command.clone());
+                    }
+                    "333" => {
+                        //Topic set by: own nick, channel, setter (nick or nick!user@host), unix time
+                        if let Some(topic) = self.server_list.get_mut(&server_id).and_then(|s| s.channels.get_mut(msg.param(1))).and_then(|c| c.topic.as_mut()) {
+                            topic.setter = Some(msg.param(2).split('!').next().unwrap_or("").to_string());
+                            topic.time = msg.param(3).parse::<i64>().ok().and_then(|t| DateTime::from_timestamp(t, 0)).map(|t| t.with_timezone(&Local));
+                        }
+                    }
+                    "433" => {
                         //HANDLE NickName in use
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
                             server.nick = server.nick.clone() + "_";
                             self.active_nick = server.nick.clone();
-                            self.stream_mgr.send_line(server_id.clone(), "NICK ".to_owned() + &server.nick);
+                            self.stream_mgr.send_line(server_id.clone(), IrcMessage::new("NICK", &[&server.nick]).to_string());
                         }
-                        //self.nick = self.nick.clone() + "_";
-                        //self.stream_mgr.send_line(server_id.clone(), "NICK ".to_owned() + &self.nick);
-                        self.chat_bounds(prefix.unwrap_or("").to_string() + " " + &command[1..], server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
                     }
-                    Some("QUIT") => {
+                    "QUIT" => {
                         //HANDLE QUIT
-                        let end_bytes = line.find('!');
-                        let result = &line[1..end_bytes.unwrap_or(0)];
-                        let target = result.trim_start_matches(|c| c == '@' || c == '+');
-                        
+                        let target = msg.nick();
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
+                            let casemap = server.casemap();
                             for (_channel_name, channel_data) in server.channels.iter_mut() {
-                                channel_data.user_list.retain(|user| {
-                                    let stripped_user = user.trim_start_matches(|c| c == '@' || c == '+');
-                                    stripped_user != target
-                                });
-
+                                channel_data.user_list.retain(|user| !casemap.eq(&user.nick, target));
                             }
                         }
-                        self.chat_bounds(prefix.unwrap_or("").to_string() + " " + &command[1..], server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
                     }
-                    Some("PART") => {
+                    "PART" => {
                         //HANDLE PART
-                        let end_bytes = line.find('!');
-                        let result = &line[1..end_bytes.unwrap_or(1)];
-                        //let start_chan = line.find('#');
-                        let mut line_split = command.split_whitespace();
-                        let chan = line_split.next();
-
+                        let target = msg.nick();
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
-                            if let Some(channel) = server.channels.get_mut(chan.unwrap_or("")) {
-                                channel.user_list.retain(|u| {
-                                let stripped = u.trim_start_matches(|c| c == '@' || c == '+');
-                                stripped != result
-                            });
-
+                            let casemap = server.casemap();
+                            if let Some(channel) = server.channels.get_mut(msg.param(0)) {
+                                channel.user_list.retain(|u| !casemap.eq(&u.nick, target));
                             }
                         }
-                        self.chat_bounds(prefix.unwrap_or("").to_string() + " " + &command[1..], server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
                     }
-                    Some("NICK") => {
+                    "NICK" => {
                         //HANDLE NICK COMMAND
-                        let end_byte = &line.find('!');
-                        let start_byte = &line.find("NICK :");
-                        let user_old = &line[1..end_byte.unwrap_or(0)];
-                        let new_user = &line[(start_byte.unwrap_or(0) + 6)..];
+                        let user_old = msg.nick();
+                        let new_user = msg.param(0);
 
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
-                            if user_old == server.nick {
+                            if server.is_own_nick(user_old) {
                                 server.nick = new_user.to_string();
                                 self.active_nick = new_user.to_string();
-                                self.chat_bounds("You're now known as ".to_owned() + &self.active_nick, server_id.clone(), self.active_channel.clone(), arg.unwrap_or("").to_string());
+                                self.chat_bounds("You're now known as ".to_owned() + &self.active_nick, server_id.clone(), self.active_channel.clone(), msg.command.clone());
                             }
                         }
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()){
+                            let casemap = server.casemap();
                             for (_channel_name, channel_data) in server.channels.iter_mut() {
                                 for user in &mut channel_data.user_list {
-                                    let stripped = user.trim_start_matches(|c| c == '@' || c == '+');
-                                    if stripped == user_old {
-                                        *user = new_user.to_string();
+                                    //The mode prefixes stay with the user
+                                    if casemap.eq(&user.nick, user_old) {
+                                        user.nick = new_user.to_string();
                                     }
                                 }
                             }
                         }
-                        self.chat_bounds(prefix.unwrap_or("").to_string() + " " + &command[1..], server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
                     }
-                    Some("JOIN") => {
-                        let end_bytes = line.find('!');
-                        let result = &line[1..end_bytes.unwrap_or(1)];
-                        let re = Regex::new(r"(#[^\s:]+)").unwrap();
-                        let chan_re = re.captures(&line).unwrap();
-
+                    "JOIN" => {
+                        let result = msg.nick();
+                        let mut chan = msg.param(0).to_string();
 
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
-                            if result == server.nick {
+                            if server.is_own_nick(result) {
+                                let casemap = server.casemap();
                                 let server_channels = &mut self.server_list.entry(server_id.clone()).or_default().channels;
-                                server_channels.entry(chan_re[1].to_owned()).or_insert(self::ChannelData { chat_list: vec![("System".to_string(),"Joining Channel".to_string())], user_list: vec![], chat_pos: 0, notification: false, });
-                                let (on, left_server, left, right_server, right) = self.split.clone();
-                                if on == true {
-                                    if self.active_channel == left {
-                                        self.split = (true, server_id.clone(), chan_re[1].to_string(), right_server, right);
-                                        self.active_channel = chan_re[1].to_owned();
-                                        self.active_server = server_id.clone();
-
-                                    } else {
-                                        self.split = (true, left_server, left, server_id.clone(), chan_re[1].to_string());
-                                        self.active_channel = chan_re[1].to_owned();
-                                        self.active_server = server_id.clone();
-                                    }
-                                } else {
-                                    self.active_channel = chan_re[1].to_owned();
-                                    self.active_server = server_id.clone();
+                                if !server_channels.contains_key(&chan) {
+                                    request_backlog(&self.logger, &self.backlog_tx, &server_id, &chan, casemap);
+                                    let chat_list = vec![ChatLine::new("System", "Joining Channel")];
+                                    server_channels.insert(chan.clone(), self::ChannelData { chat_list, ..Default::default() });
+                                }
+                                //An open buffer keeps the casing it was opened with
+                                chan = server_channels.key(&chan).cloned().unwrap_or(chan);
+                                if let Some(channel) = server_channels.get_mut(&chan) {
+                                    channel.parted = false;
                                 }
+                                //Channel modes come back in 324
+                                self.stream_mgr.send_line(server_id.clone(), IrcMessage::new("MODE", &[&chan]).to_string());
+                                self.show_buffer(&server_id, &chan);
                             }
                         }
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
-                            if let Some(channel) = server.channels.get_mut(&chan_re[1].to_owned()) {
-                                if result != server.nick {
-                                    if !channel.user_list.contains(&result.to_string()) {
-                                        channel.user_list.push(result.to_string());
-                                    }
+                            let own = server.is_own_nick(result);
+                            let casemap = server.casemap();
+                            if let Some(channel) = server.channels.get_mut(&chan) {
+                                if !own && !channel.user_list.iter().any(|u| casemap.eq(&u.nick, result)) {
+                                    channel.user_list.push(User::new(result));
                                 }
                             }
                         }
-                        self.chat_bounds(prefix.unwrap_or("").to_string() + " " + &command[1..], server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
-                    }
-                    Some("353") => {
-                        let start_bytes = command.find(':');
-                        let result = &command[start_bytes.unwrap_or(0) + 1..];
-                        let list_result = result.split_whitespace();
-                        let re = Regex::new(r"(#[^\s:]+)").unwrap();
-                        let chan_re = re.captures(&command).unwrap();
+                        self.chat_bounds(source + " " + &msg.display_params(), server_id.clone(), "Status".to_string(), msg.command.clone());
+                    }
+                    "353" => {
+                        //NAMES reply: own nick, channel type, channel, names
                         if let Some(server) = self.server_list.get_mut(&server_id.clone()) {
-                            if let Some(channel) = server.channels.get_mut(&chan_re[1].to_owned()) {
-                                for i in list_result {
-                                    if !channel.user_list.contains(&i.to_string()) {
-                                        channel.user_list.push(i.to_string());
+                            let casemap = server.casemap();
+                            if let Some(channel) = server.channels.get_mut(msg.param(2)) {
+                                for name in msg.param(3).split_whitespace() {
+                                    let user = server.mode_table.parse_user(name);
+                                    match channel.user_list.iter_mut().find(|u| casemap.eq(&u.nick, &user.nick)) {
+                                        Some(existing) => existing.prefixes = user.prefixes,
+                                        None => channel.user_list.push(user),
                                     }
                                 }
                             }
                         }
                     }
-                    Some("PRIVMSG") => {
-                        let end_bytes = line.find('!');
-                        let res_nick = &line[1..end_bytes.unwrap_or(0)];
-                        let end_mess = line.find(&(" :"));
-                        let msg_start = line.find(&("PRIVMSG "));
-                        let msg_chan = &line[msg_start.unwrap_or(0) + 8..end_mess.unwrap_or(0)];
-                        let msg = line[(end_mess.unwrap_or(0) + 2)..].to_string();
-                        if msg_chan.starts_with('#') {
-                            self.chat_bounds(msg.clone(), server_id.clone(), msg_chan.to_string(), res_nick.to_string());
+                    "AWAY" => {
+                        //away-notify, a message means away and none means back
+                        let nick = msg.nick();
+                        let away = !msg.params.is_empty();
+                        if let Some(server) = self.server_list.get_mut(&server_id) {
+                            let casemap = server.casemap();
+                            for (_channel_name, channel_data) in server.channels.iter_mut() {
+                                if let Some(user) = channel_data.user_list.iter_mut().find(|u| casemap.eq(&u.nick, nick)) {
+                                    user.away = away;
+                                }
+                            }
+                        }
+                    }
+                    "PRIVMSG" => {
+                        let res_nick = msg.nick();
+                        let msg_chan = msg.param(0);
+                        let text = msg.param(1).to_string();
+                        let from_self = self.server_list.get(&server_id).is_some_and(|s| s.is_own_nick(res_nick));
+                        let casemap = self.server_list.get(&server_id).map(|s| s.casemap()).unwrap_or_default();
+                        let to_channel = self.is_channel(&server_id, msg_chan);
+                        if let Some((verb, args)) = ctcp::parse(&text) {
+                            //Channel, echo of our own message, or a query buffer for the sender
+                            let buffer = if to_channel || from_self { msg_chan } else { res_nick };
+                            if verb == "ACTION" {
+                                self.chat_action(args, server_id.clone(), buffer.to_string(), res_nick.to_string());
+                            } else if !from_self {
+                                if let Some(reply) = self.ctcp.reply(&server_id, &verb, &args) {
+                                    self.stream_mgr.send_line(server_id.clone(), IrcMessage::new("NOTICE", &[res_nick, &reply]).to_string());
+                                }
+                                self.chat_bounds(format!("CTCP {} from {}", verb, res_nick), server_id.clone(), "Status".to_string(), "CTCP".to_string());
+                            }
+                        } else if to_channel {
+                            self.chat_message(text, server_id.clone(), msg_chan.to_string(), res_nick.to_string());
+                        } else if from_self {
+                            //echo-message of our own private message
+                            self.chat_message(text, server_id.clone(), msg_chan.to_string(), res_nick.to_string());
                         } else {
-                            let msg = msg_chan.to_string() + "-> " + &line[(end_mess.unwrap_or(0) + 2)..];
-                            self.chat_bounds(msg.clone(), server_id.clone(), res_nick.to_string(), res_nick.to_string());
-                            if self.active_channel != res_nick {
-                                self.chat_bounds(msg.clone(), server_id.clone(), self.active_channel.clone(), res_nick.to_string());
+                            let text = msg_chan.to_string() + "-> " + &text;
+                            self.chat_message(text.clone(), server_id.clone(), res_nick.to_string(), res_nick.to_string());
+                            if !casemap.eq(&self.active_channel, res_nick) {
+                                self.chat_bounds(text.clone(), server_id.clone(), self.active_channel.clone(), res_nick.to_string());
                             }
                         }
                     }
                     _ => {
-                        if !line.is_empty() && line.starts_with(':') {
-                            let command_trim: String = command[1..].chars().filter(|c| !c.is_control()).collect();
-                            self.chat_bounds(command_trim, server_id.clone(), "Status".to_string(), arg.unwrap_or("").to_string());
+                        if msg.source.is_some() {
+                            let command_trim: String = msg.display_params().chars().filter(|c| !c.is_control()).collect();
+                            self.chat_bounds(command_trim, server_id.clone(), "Status".to_string(), msg.command.clone());
                         }
                     }
-                } 
-            } 
+                }
+                self.msg_time = None;
+            }
+            NetEvent::Caps(caps) => {
+                if let Some(server) = self.server_list.get_mut(&server_id) {
+                    server.caps = caps;
+                }
+            }
+            NetEvent::Status(s) => {
+                self.chat_bounds(s, server_id, "Status".to_string(), "System".to_string());
+            }
             NetEvent::Error(e)   => {
                 self.chat_bounds(e.to_string(), self.active_server.clone(), self.active_channel.clone(), "Error".to_string());
             }
         }
     }
 
-    pub fn quit(&mut self) {
+    // By the server's CHANTYPES, "#&" before it is known
+    pub fn is_channel(&self, server_id: &str, name: &str) -> bool {
+        match self.server_list.get(server_id) {
+            Some(server) => server.isupport.is_channel(name),
+            None => ISupport::default().is_channel(name),
+        }
+    }
+
+    // Prefix modes go to the user list, list modes (bans and such) are not kept
+    fn apply_channel_modes(&mut self, server_id: &str, chan: &str, changes: &[ModeChange]) {
+        let server = match self.server_list.get_mut(server_id) {
+            Some(s) => s,
+            None => return,
+        };
+        let channel = match server.channels.get_mut(chan) {
+            Some(c) => c,
+            None => return,
+        };
+        for change in changes {
+            if let Some(symbol) = server.mode_table.symbol(change.mode) {
+                let nick = change.arg.as_deref().unwrap_or("");
+                if let Some(user) = channel.user_list.iter_mut().find(|u| server.isupport.casemapping.eq(&u.nick, nick)) {
+                    if change.set {
+                        server.mode_table.add_prefix(user, symbol);
+                    } else {
+                        server.mode_table.remove_prefix(user, symbol);
+                    }
+                }
+            } else if server.mode_table.is_list(change.mode) {
+                continue;
+            } else if change.set {
+                channel.modes.insert(change.mode, change.arg.clone());
+            } else {
+                channel.modes.remove(&change.mode);
+            }
+        }
+    }
+
+    pub fn is_ignored(&self, server_id: &str, nick: &str) -> bool {
+        let casemap = self.server_list.get(server_id).map(|s| s.casemap()).unwrap_or_default();
+        self.ignores.iter().any(|i| casemap.eq(i, nick))
+    }
+
+    pub fn ignore(&mut self, nick: &str) {
+        if !self.is_ignored(&self.active_server, nick) {
+            self.ignores.push(nick.to_string());
+        }
+        self.chat_bounds(format!("Ignoring {}", nick), self.active_server.clone(), self.active_channel.clone(), "System".to_string());
+    }
+
+    // Opens a private message buffer without sending anything and makes it active
+    pub fn open_query(&mut self, server_id: &str, nick: &str) {
+        if let Some(server) = self.server_list.get_mut(server_id) {
+            if !server.channels.contains_key(nick) {
+                request_backlog(&self.logger, &self.backlog_tx, server_id, nick, server.casemap());
+                server.channels.insert(nick.to_string(), ChannelData::default());
+            }
+            let channel = server.channels.key(nick).cloned().unwrap_or(nick.to_string());
+            self.show_buffer(server_id, &channel);
+        }
+    }
+
+    // Users of a channel, highest rank first then by nick
+    pub fn users_by_rank(&self, server_id: &str, channel_id: &str) -> Vec<&User> {
+        let server = match self.server_list.get(server_id) {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        let mut users: Vec<&User> = server.channels.get(channel_id).map(|c| c.user_list.iter().collect()).unwrap_or_default();
+        users.sort_by_key(|u| server.mode_table.sort_key(u));
+        users
+    }
+
+    // Rows left for chat lines in the buffer's pane, the topic bar takes one when the buffer has a topic
+    pub fn chat_rows(&self, server_id: &str, channel_id: &str) -> usize {
+        let topic = self.server_list.get(server_id).and_then(|s| s.channels.get(channel_id)).is_some_and(|c| c.topic.is_some());
+        let height = match self.pane_of(server_id, channel_id).and_then(|i| self.layout.rects.get(i)) {
+            Some(rect) => rect.height as usize,
+            None => (self.h as usize).saturating_sub(4),
+        };
+        height.saturating_sub(if topic { 3 } else { 2 })
+    }
+
+    // Buffer name with the channel modes, "#rust +nt"
+    pub fn buffer_title(&self, server_id: &str, channel_id: &str) -> String {
+        let modes = self.server_list.get(server_id)
+            .and_then(|s| s.channels.get(channel_id))
+            .map(|c| mode::format_channel_modes(&c.modes))
+            .unwrap_or_default();
+        let parted = self.server_list.get(server_id).and_then(|s| s.channels.get(channel_id)).is_some_and(|c| c.parted);
+        let title = if modes.is_empty() { channel_id.to_string() } else { format!("{} {}", channel_id, modes) };
+        if parted { format!("{} (parted)", title) } else { title }
+    }
+
+    // The backlog goes in front of what the buffer got since it was opened, closed by an end of backlog separator
+    pub fn backlog_loaded(&mut self, backlog: Backlog) {
+        let channel = self.server_list.get_mut(&backlog.server).and_then(|s| s.channels.get_mut(&backlog.channel));
+        if let Some(channel) = channel {
+            let mut lines = backlog.lines;
+            lines.push(ChatLine { kind: LineKind::BacklogEnd, ..ChatLine::new("", "— end of backlog —") });
+            lines.append(&mut channel.chat_list);
+            channel.chat_list = lines;
+        }
+    }
+
+    pub async fn quit(&mut self) {
+        self.layout.sync(&self.active_server, &self.active_channel);
+        layout::save(&self.layout);
         self.stream_mgr.disconnect_all();
+        // Lines still queued for the log would be lost once the runtime goes away
+        self.logger.shutdown().await;
         self.exit = true;
     }
 
+    // Width of the "time nick: " column in front of chat lines
+    pub fn prefix_width(&self) -> usize {
+        let stamp = Local::now().format(&self.timestamp_format).to_string();
+        let stamp_width = if stamp.is_empty() { 0 } else { stamp.chars().count() + 1 };
+        stamp_width + 12
+    }
+
+    // Width of the chat windows, the sidebar and nicklist take their part of the terminal
+    pub fn chat_width(&self) -> u16 {
+        let mut width = self.w;
+        if self.sidebar {
+            width = width.saturating_sub(self.sidebar_width);
+        }
+        if self.nicklist_shown() {
+            width = width.saturating_sub(self.nicklist_width);
+        }
+        width
+    }
+
+    // Only for a channel, not in split view and not in a narrow terminal
+    pub fn nicklist_shown(&self) -> bool {
+        self.nicklist && !self.layout.is_split() && self.w >= self.nicklist_min_width && self.is_channel(&self.active_server, &self.active_channel)
+    }
+
+    // Server replies and events, a line in another buffer marks it with Activity::Events
     pub fn chat_bounds(&mut self, data: String, server_id: String, channel_id: String, nick: String) {
+        self.add_line(LineKind::Message, Activity::Events, data, server_id, channel_id, nick);
+    }
+
+    // A message from someone, counted as unread in another buffer
+    pub fn chat_message(&mut self, data: String, server_id: String, channel_id: String, nick: String) {
+        let level = self.message_level(&data, &server_id, &channel_id, &nick);
+        self.add_line(LineKind::Message, level, data, server_id, channel_id, nick);
+    }
+
+    // CTCP ACTION, shown as "* nick text"
+    pub fn chat_action(&mut self, data: String, server_id: String, channel_id: String, nick: String) {
+        let level = self.message_level(&data, &server_id, &channel_id, &nick);
+        self.add_line(LineKind::Action, level, data, server_id, channel_id, nick);
+    }
+
+    fn message_level(&self, data: &str, server_id: &str, channel_id: &str, nick: &str) -> Activity {
+        match self.server_list.get(server_id) {
+            Some(server) if server.is_own_nick(nick) => Activity::None,
+            Some(server) if server.mentions(data) || !server.isupport.is_channel(channel_id) => Activity::Highlight,
+            _ => Activity::Messages,
+        }
+    }
+
+    // Pane showing a buffer, the focused pane shows the active one
+    pub fn pane_of(&self, server_id: &str, channel_id: &str) -> Option<usize> {
+        if self.active_server == server_id && self.active_channel == channel_id {
+            return Some(self.layout.focus);
+        }
+        self.layout.find(server_id, channel_id).filter(|i| *i != self.layout.focus)
+    }
+
+    pub fn is_visible(&self, server_id: &str, channel_id: &str) -> bool {
+        self.pane_of(server_id, channel_id).is_some()
+    }
+
+    // Width of the pane a buffer is shown in, the whole chat area when it is not shown
+    pub fn pane_width(&self, server_id: &str, channel_id: &str) -> u16 {
+        match self.pane_of(server_id, channel_id).and_then(|i| self.layout.rects.get(i)) {
+            Some(rect) => rect.width,
+            None => self.chat_width(),
+        }
+    }
+
+    // Focuses a pane and makes its buffer active
+    pub fn focus_pane(&mut self, index: usize) {
+        self.layout.sync(&self.active_server, &self.active_channel);
+        if let Some(pane) = self.layout.focus_pane(index) {
+            self.switch_buffer(&pane.server, &pane.channel);
+        }
+    }
+
+    // Focuses the pane that already shows a buffer, otherwise shows it in the focused pane
+    pub fn show_buffer(&mut self, server_id: &str, channel_id: &str) {
+        self.layout.sync(&self.active_server, &self.active_channel);
+        match self.layout.find(server_id, channel_id) {
+            Some(index) => self.focus_pane(index),
+            None => self.switch_buffer(server_id, channel_id),
+        }
+    }
+
+    // Panes of a closed buffer, or of every buffer of a disconnected server, show Status instead
+    pub fn buffer_closed(&mut self, server_id: &str, channel_id: Option<&str>) {
+        self.layout.sync(&self.active_server, &self.active_channel);
+        self.layout.buffer_closed(server_id, channel_id);
+        if self.active_server == server_id && channel_id.is_none_or(|c| self.active_channel == c) {
+            let focus = self.layout.focus;
+            self.focus_pane(focus);
+        }
+    }
+
+    // Makes a buffer the active one in the focused pane
+    pub fn switch_buffer(&mut self, server_id: &str, channel_id: &str) {
+        self.active_server = server_id.to_string();
+        self.active_channel = channel_id.to_string();
+        if let Some(server) = self.server_list.get_mut(server_id) {
+            self.active_nick = server.nick.clone();
+            if let Some(channel) = server.channels.get_mut(channel_id) {
+                channel.chat_pos = 0;
+                channel.mark_read();
+            }
+        }
+    }
+
+    fn add_line(&mut self, kind: LineKind, level: Activity, data: String, server_id: String, channel_id: String, nick: String) {
+        let time = self.msg_time.unwrap_or_else(Local::now);
+        let line = ChatLine { kind, ..ChatLine::at(time, &nick, &data) };
+        let prefix_width = self.prefix_width();
+        //Same buffer whatever the casing, shown as it was opened
+        let channel_id = self.server_list.get(&server_id).and_then(|s| s.channels.key(&channel_id)).cloned().unwrap_or(channel_id);
+        let visible = self.is_visible(&server_id, &channel_id);
+        let wrap_width = textstyle::wrap_width(self, &server_id, &channel_id, prefix_width);
 
         //Limit length of list
         if let Some(server) = self.server_list.get_mut(&server_id) {
@@ -383,29 +921,23 @@ impl App {
 
         
         if let Some(server) = self.server_list.get_mut(&server_id) {
-            match server.channels.entry(channel_id.clone()) {
-                Entry::Occupied(mut entry) => {
-                    entry.get_mut().chat_list.push((nick, data.clone()));
-                    let (on, _left_server, left_chan, _right_server, right_chan) = self.split.clone();
+            match server.channels.get_mut(&channel_id) {
+                Some(entry) => {
+                    let chat_list = &mut entry.chat_list;
+                    if let Some(last) = chat_list.last() {
+                        if last.time.date_naive() != time.date_naive() {
+                            let day = time.format("%A %-d %B %Y").to_string();
+                            chat_list.push(ChatLine { kind: LineKind::DayChange, ..ChatLine::at(time, "", &day) });
+                        }
+                    }
+                    chat_list.push(line.clone());
                     //if self.active_server == server_id && self.active_channel == channel_id {
                         if let Some(server) = self.server_list.get_mut(&server_id) {
                             if let Some(channel) = server.channels.get_mut(&channel_id) {
                                  
-                                let wrap_width;
                                 //chat window horizontal "linewrap"
-                                //let (on, _, _, _, _) = self.split;
-                                if on == true {
-                                    wrap_width = (self.w as usize / 2) - 6 - 12;
-                                    if channel_id != left_chan {
-                                        if channel_id != right_chan {
-                                            channel.notification = true;
-                                        }
-                                    }
-                                } else {
-                                    wrap_width = self.w as usize-4 - 12;
-                                    if self.active_channel != channel_id {
-                                        channel.notification = true;
-                                    }
+                                if !visible {
+                                    channel.mark(level);
                                 }
                                 let wrap_options = Options::new(wrap_width).break_words(false);
                                 let wrapped_line = wrap(&data, wrap_options);
@@ -419,15 +951,31 @@ impl App {
                     //}
                 }
 
-                Entry::Vacant(entry) => {
-                    entry.insert(self::ChannelData {
-                        chat_list: vec![(nick, data)],
-                        user_list: vec![],
-                        chat_pos: 0,
-                        notification: false,
-                    });
+                None => {
+                    // New query buffer, show what was said before
+                    if server_id != "System" {
+                        request_backlog(&self.logger, &self.backlog_tx, &server_id, &channel_id, server.casemap());
+                    }
+                    let mut channel = ChannelData { chat_list: vec![line.clone()], ..Default::default() };
+                    if !visible {
+                        channel.mark(level);
+                    }
+                    server.channels.insert(channel_id.clone(), channel);
                 }
             }
         }
+
+        // The System server only holds local messages, logged after a new buffer read its backlog
+        if server_id != "System" {
+            let casemap = self.server_list.get(&server_id).map(|s| s.casemap()).unwrap_or_default();
+            self.logger.log(&server_id, &channel_id, &line, casemap);
+        }
     } 
 }
+
+// Logged lines of a buffer that is being opened, read by the logger task and handled in backlog_loaded
+fn request_backlog(logger: &Logger, reply: &Option<mpsc::UnboundedSender<Backlog>>, server_id: &str, channel_id: &str, casemap: CaseMapping) {
+    if let Some(reply) = reply {
+        logger.request_backlog(server_id, channel_id, casemap, reply);
+    }
+}
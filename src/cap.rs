@@ -0,0 +1,328 @@
+// cap.rs
+use std::fmt;
+use base64::Engine;
+use crate::message::IrcMessage;
+
+// Requested on every server unless the server entry lists its own caps
+pub const DEFAULT_CAPS: [&str; 4] = ["multi-prefix", "server-time", "away-notify", "echo-message"];
+pub const TWITCH_CAPS: [&str; 1] = ["twitch.tv/membership"];
+
+#[derive(Clone)]
+pub enum Sasl {
+    Plain { account: String, password: String },
+    External,
+}
+
+// The password never shows up in a {:?}
+impl fmt::Debug for Sasl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sasl::Plain { account, .. } => f.debug_struct("Plain").field("account", account).field("password", &"<redacted>").finish(),
+            Sasl::External => write!(f, "External"),
+        }
+    }
+}
+
+impl Sasl {
+    fn mechanism(&self) -> &'static str {
+        match self {
+            Sasl::Plain { .. } => "PLAIN",
+            Sasl::External => "EXTERNAL",
+        }
+    }
+
+    // AUTHENTICATE payload lines, base64 split in 400 byte chunks, "+" for empty or to end an exact multiple
+    fn payload(&self) -> Vec<String> {
+        let raw = match self {
+            Sasl::Plain { account, password } => format!("{account}\0{account}\0{password}"),
+            Sasl::External => String::new(),
+        };
+        if raw.is_empty() {
+            return vec![IrcMessage::new("AUTHENTICATE", &["+"]).to_string()];
+        }
+        let encoded = base64::engine::general_purpose::STANDARD.encode(raw);
+        let mut lines: Vec<String> = encoded.as_bytes().chunks(400).map(|c| IrcMessage::new("AUTHENTICATE", &[std::str::from_utf8(c).unwrap_or("")]).to_string()).collect();
+        if encoded.len() % 400 == 0 {
+            lines.push(IrcMessage::new("AUTHENTICATE", &["+"]).to_string());
+        }
+        lines
+    }
+}
+
+pub enum CapAction {
+    Send(Vec<String>),
+    Abort(String),
+    None,
+}
+
+// CAP LS/REQ/ACK/NAK/NEW/DEL negotiation, with SASL on top when configured
+pub struct CapNegotiation {
+    desired: Vec<String>,
+    sasl: Option<Sasl>,
+    ls_caps: Vec<String>,
+    pub enabled: Vec<String>,
+    pending_reqs: usize,
+    authenticating: bool,
+    // CAP END sent, or the server never answered CAP LS
+    ended: bool,
+}
+
+impl CapNegotiation {
+    pub fn new(desired: Vec<String>, sasl: Option<Sasl>) -> CapNegotiation {
+        CapNegotiation { desired, sasl, ls_caps: Vec::new(), enabled: Vec::new(), pending_reqs: 0, authenticating: false, ended: false }
+    }
+
+    // Lines sent before NICK/USER, holds registration until CAP END
+    pub fn start(&self) -> Vec<String> {
+        vec![IrcMessage::new("CAP", &["LS", "302"]).to_string()]
+    }
+
+    // Twitch does not answer CAP LS, so request straight away
+    pub fn start_twitch(&mut self) -> Vec<String> {
+        self.ended = true;
+        self.request(self.desired.clone())
+    }
+
+    pub fn on_message(&mut self, msg: &IrcMessage) -> CapAction {
+        match msg.command.as_str() {
+            "CAP" => self.on_cap(msg),
+            "AUTHENTICATE" if self.authenticating => {
+                match (&self.sasl, msg.param(0)) {
+                    (Some(sasl), "+") => CapAction::Send(sasl.payload()),
+                    _ => CapAction::None,
+                }
+            }
+            "903" if self.authenticating => {
+                self.authenticating = false;
+                self.end()
+            }
+            "902" | "904" | "905" | "906" if self.authenticating => {
+                self.authenticating = false;
+                CapAction::Abort(format!("SASL authentication failed: {}", msg.trailing()))
+            }
+            "001" if !self.ended => {
+                // Server without CAP support went straight through registration
+                self.ended = true;
+                if self.sasl.is_some() {
+                    return CapAction::Abort("Server does not support SASL".to_string());
+                }
+                CapAction::None
+            }
+            _ => CapAction::None,
+        }
+    }
+
+    fn on_cap(&mut self, msg: &IrcMessage) -> CapAction {
+        let caps: Vec<String> = msg.trailing().split_whitespace().map(|c| c.to_string()).collect();
+        match msg.param(1) {
+            "LS" => {
+                self.ls_caps.extend(caps);
+                // "CAP * LS * :..." means more lines are coming
+                if msg.params.len() > 3 && msg.param(2) == "*" {
+                    return CapAction::None;
+                }
+                if let Some(sasl) = &self.sasl {
+                    match self.ls_caps.iter().find(|c| cap_name(c) == "sasl") {
+                        Some(cap) => {
+                            if let Some((_, mechs)) = cap.split_once('=') {
+                                if !mechs.split(',').any(|m| m.eq_ignore_ascii_case(sasl.mechanism())) {
+                                    return CapAction::Abort(format!("SASL {} not supported by server (offers {})", sasl.mechanism(), mechs));
+                                }
+                            }
+                        }
+                        None => return CapAction::Abort("Server does not support SASL".to_string()),
+                    }
+                }
+                let mut wanted = self.wanted(&self.ls_caps);
+                if self.sasl.is_some() {
+                    wanted.push("sasl".to_string());
+                }
+                if wanted.is_empty() {
+                    return self.end();
+                }
+                CapAction::Send(self.request(wanted))
+            }
+            "ACK" => {
+                self.pending_reqs = self.pending_reqs.saturating_sub(1);
+                for cap in caps {
+                    // "-cap" in an ACK means it got disabled
+                    if let Some(removed) = cap.strip_prefix('-') {
+                        self.enabled.retain(|c| c != removed);
+                    } else if !self.enabled.contains(&cap) {
+                        self.enabled.push(cap);
+                    }
+                }
+                if !self.ended && self.sasl.is_some() && !self.authenticating && self.enabled.iter().any(|c| c == "sasl") {
+                    self.authenticating = true;
+                    let mech = self.sasl.as_ref().map(|s| s.mechanism()).unwrap_or("PLAIN");
+                    return CapAction::Send(vec![IrcMessage::new("AUTHENTICATE", &[mech]).to_string()]);
+                }
+                self.end_if_settled()
+            }
+            "NAK" => {
+                self.pending_reqs = self.pending_reqs.saturating_sub(1);
+                if !self.ended && self.sasl.is_some() && caps.iter().any(|c| c == "sasl") {
+                    return CapAction::Abort("Server refused the sasl capability".to_string());
+                }
+                self.end_if_settled()
+            }
+            "NEW" => {
+                let wanted = self.wanted(&caps);
+                if wanted.is_empty() {
+                    return CapAction::None;
+                }
+                CapAction::Send(self.request(wanted))
+            }
+            "DEL" => {
+                self.enabled.retain(|c| !caps.iter().any(|d| cap_name(d) == c));
+                CapAction::None
+            }
+            _ => CapAction::None,
+        }
+    }
+
+    // Desired caps out of an offered list, skipping what is already enabled
+    fn wanted(&self, offered: &[String]) -> Vec<String> {
+        offered.iter()
+            .map(|c| cap_name(c).to_string())
+            .filter(|c| self.desired.contains(c) && !self.enabled.contains(c))
+            .collect()
+    }
+
+    fn request(&mut self, caps: Vec<String>) -> Vec<String> {
+        self.pending_reqs += 1;
+        vec![IrcMessage::new("CAP", &["REQ", &caps.join(" ")]).to_string()]
+    }
+
+    fn end_if_settled(&mut self) -> CapAction {
+        if self.pending_reqs == 0 && !self.authenticating {
+            return self.end();
+        }
+        CapAction::None
+    }
+
+    fn end(&mut self) -> CapAction {
+        if self.ended {
+            return CapAction::None;
+        }
+        self.ended = true;
+        CapAction::Send(vec![IrcMessage::new("CAP", &["END"]).to_string()])
+    }
+}
+
+// "sasl=PLAIN,EXTERNAL" -> "sasl"
+fn cap_name(cap: &str) -> &str {
+    cap.split_once('=').map(|(n, _)| n).unwrap_or(cap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(list: &[&str]) -> Vec<String> {
+        list.iter().map(|c| c.to_string()).collect()
+    }
+
+    // Feeds one server line, what the negotiation answers with
+    fn feed(neg: &mut CapNegotiation, line: &str) -> CapAction {
+        neg.on_message(&IrcMessage::parse(line).unwrap())
+    }
+
+    fn sent(action: CapAction) -> Vec<String> {
+        match action {
+            CapAction::Send(lines) => lines,
+            CapAction::Abort(reason) => panic!("aborted: {reason}"),
+            CapAction::None => Vec::new(),
+        }
+    }
+
+    fn aborted(action: CapAction) -> String {
+        match action {
+            CapAction::Abort(reason) => reason,
+            _ => panic!("expected an abort"),
+        }
+    }
+
+    #[test]
+    fn multiline_ls_waits_for_the_last_line() {
+        let mut neg = CapNegotiation::new(caps(&["multi-prefix", "server-time", "away-notify"]), None);
+        assert_eq!(neg.start(), vec!["CAP LS 302"]);
+        assert!(sent(feed(&mut neg, ":srv CAP * LS * :multi-prefix sasl=PLAIN,EXTERNAL")).is_empty());
+        assert_eq!(sent(feed(&mut neg, ":srv CAP * LS :server-time batch")), vec!["CAP REQ :multi-prefix server-time"]);
+        assert_eq!(sent(feed(&mut neg, ":srv CAP me ACK :multi-prefix server-time")), vec!["CAP END"]);
+        assert_eq!(neg.enabled, caps(&["multi-prefix", "server-time"]));
+    }
+
+    #[test]
+    fn nothing_wanted_ends_right_away() {
+        let mut neg = CapNegotiation::new(caps(&["echo-message"]), None);
+        assert_eq!(sent(feed(&mut neg, ":srv CAP * LS :multi-prefix")), vec!["CAP END"]);
+        // A second END is never sent
+        assert!(sent(feed(&mut neg, ":srv 001 me :Welcome")).is_empty());
+    }
+
+    #[test]
+    fn nak_still_ends() {
+        let mut neg = CapNegotiation::new(caps(&["multi-prefix"]), None);
+        sent(feed(&mut neg, ":srv CAP * LS :multi-prefix"));
+        assert_eq!(sent(feed(&mut neg, ":srv CAP me NAK :multi-prefix")), vec!["CAP END"]);
+        assert!(neg.enabled.is_empty());
+    }
+
+    #[test]
+    fn new_and_del() {
+        let mut neg = CapNegotiation::new(caps(&["multi-prefix", "away-notify"]), None);
+        sent(feed(&mut neg, ":srv CAP * LS :multi-prefix"));
+        sent(feed(&mut neg, ":srv CAP me ACK :multi-prefix"));
+        assert_eq!(sent(feed(&mut neg, ":srv CAP me NEW :away-notify chghost")), vec!["CAP REQ away-notify"]);
+        sent(feed(&mut neg, ":srv CAP me ACK :away-notify"));
+        assert_eq!(neg.enabled, caps(&["multi-prefix", "away-notify"]));
+        // Already enabled caps are not asked for again
+        assert!(sent(feed(&mut neg, ":srv CAP me NEW :away-notify")).is_empty());
+        assert!(sent(feed(&mut neg, ":srv CAP me DEL :multi-prefix")).is_empty());
+        assert_eq!(neg.enabled, caps(&["away-notify"]));
+        sent(feed(&mut neg, ":srv CAP me ACK :-away-notify"));
+        assert!(neg.enabled.is_empty());
+    }
+
+    #[test]
+    fn sasl_plain() {
+        let sasl = Sasl::Plain { account: "acct".to_string(), password: "secret".to_string() };
+        let mut neg = CapNegotiation::new(caps(&["multi-prefix"]), Some(sasl));
+        assert_eq!(sent(feed(&mut neg, ":srv CAP * LS :multi-prefix sasl=PLAIN,EXTERNAL")), vec!["CAP REQ :multi-prefix sasl"]);
+        assert_eq!(sent(feed(&mut neg, ":srv CAP me ACK :multi-prefix sasl")), vec!["AUTHENTICATE PLAIN"]);
+        // base64 of "acct\0acct\0secret"
+        assert_eq!(sent(feed(&mut neg, "AUTHENTICATE +")), vec!["AUTHENTICATE YWNjdABhY2N0AHNlY3JldA=="]);
+        assert_eq!(sent(feed(&mut neg, ":srv 903 me :SASL authentication successful")), vec!["CAP END"]);
+    }
+
+    #[test]
+    fn sasl_abort_paths() {
+        let plain = || Some(Sasl::Plain { account: "a".to_string(), password: "p".to_string() });
+
+        let mut neg = CapNegotiation::new(Vec::new(), plain());
+        assert_eq!(aborted(feed(&mut neg, ":srv CAP * LS :multi-prefix")), "Server does not support SASL");
+
+        let mut neg = CapNegotiation::new(Vec::new(), plain());
+        assert!(aborted(feed(&mut neg, ":srv CAP * LS :sasl=EXTERNAL")).contains("PLAIN not supported"));
+
+        let mut neg = CapNegotiation::new(Vec::new(), plain());
+        sent(feed(&mut neg, ":srv CAP * LS :sasl"));
+        assert_eq!(aborted(feed(&mut neg, ":srv CAP me NAK :sasl")), "Server refused the sasl capability");
+
+        let mut neg = CapNegotiation::new(Vec::new(), plain());
+        sent(feed(&mut neg, ":srv CAP * LS :sasl"));
+        sent(feed(&mut neg, ":srv CAP me ACK :sasl"));
+        assert!(aborted(feed(&mut neg, ":srv 904 me :SASL authentication failed")).starts_with("SASL authentication failed"));
+
+        // No CAP support at all, the server registers us straight away
+        let mut neg = CapNegotiation::new(Vec::new(), plain());
+        assert_eq!(aborted(feed(&mut neg, ":srv 001 me :Welcome")), "Server does not support SASL");
+    }
+
+    #[test]
+    fn debug_hides_the_password() {
+        let text = format!("{:?}", Sasl::Plain { account: "acct".to_string(), password: "hunter2".to_string() });
+        assert!(text.contains("acct") && !text.contains("hunter2"));
+    }
+}
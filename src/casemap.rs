@@ -0,0 +1,177 @@
+// casemap.rs
+use std::collections::BTreeMap;
+
+// CASEMAPPING from ISUPPORT, which nick and channel names the server treats as equal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseMapping {
+    Ascii,
+    // {}|^ are the lower case of []\~
+    #[default]
+    Rfc1459,
+    // Like rfc1459 without ^ and ~
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    // Unknown mappings compare only ascii letters, that never merges names the server keeps apart
+    pub fn parse(value: &str) -> CaseMapping {
+        match value.to_ascii_lowercase().as_str() {
+            "rfc1459" => CaseMapping::Rfc1459,
+            "strict-rfc1459" => CaseMapping::StrictRfc1459,
+            _ => CaseMapping::Ascii,
+        }
+    }
+
+    fn lower(self, c: char) -> char {
+        match (self, c) {
+            (_, 'A'..='Z') => c.to_ascii_lowercase(),
+            (CaseMapping::Ascii, _) => c,
+            (_, '[') => '{',
+            (_, ']') => '}',
+            (_, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    pub fn normalize(self, name: &str) -> String {
+        name.chars().map(|c| self.lower(c)).collect()
+    }
+
+    pub fn eq(self, a: &str, b: &str) -> bool {
+        a.len() == b.len() && a.chars().zip(b.chars()).all(|(x, y)| self.lower(x) == self.lower(y))
+    }
+}
+
+// Map keyed by the normalized name, keeping the name as it was first seen for display
+pub struct CaseMap<V> {
+    casemap: CaseMapping,
+    map: BTreeMap<String, (String, V)>,
+}
+
+impl<V> Default for CaseMap<V> {
+    fn default() -> Self {
+        Self { casemap: CaseMapping::default(), map: BTreeMap::new() }
+    }
+}
+
+impl<V> CaseMap<V> {
+    // Rekeys everything when the server announces its casemapping, the first of two names that now collide is kept
+    pub fn set_casemapping(&mut self, casemap: CaseMapping) {
+        if casemap == self.casemap {
+            return;
+        }
+        self.casemap = casemap;
+        let old = std::mem::take(&mut self.map);
+        for (_, (name, value)) in old {
+            self.map.entry(casemap.normalize(&name)).or_insert((name, value));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&V> {
+        self.map.get(&self.casemap.normalize(name)).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut V> {
+        self.map.get_mut(&self.casemap.normalize(name)).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.map.contains_key(&self.casemap.normalize(name))
+    }
+
+    // Name as displayed for an existing entry
+    pub fn key(&self, name: &str) -> Option<&String> {
+        self.map.get(&self.casemap.normalize(name)).map(|(n, _)| n)
+    }
+
+    // Replaces the value of an existing entry but keeps its display name
+    pub fn insert(&mut self, name: String, value: V) -> Option<V> {
+        match self.map.get_mut(&self.casemap.normalize(&name)) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                self.map.insert(self.casemap.normalize(&name), (name, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<V> {
+        self.map.remove(&self.casemap.normalize(name)).map(|(_, v)| v)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.map.values().map(|(n, _)| n)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.map.values().map(|(n, v)| (n, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut V)> {
+        self.map.values_mut().map(|(n, v)| (&*n, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc1459_folds_brackets() {
+        let rfc = CaseMapping::Rfc1459;
+        assert_eq!(rfc.normalize("Nick[]\\~"), "nick{}|^");
+        assert!(rfc.eq("#FOO[]", "#foo{}"));
+        assert!(rfc.eq("a~", "A^"));
+    }
+
+    #[test]
+    fn strict_rfc1459_keeps_tilde() {
+        let strict = CaseMapping::StrictRfc1459;
+        assert_eq!(strict.normalize("Nick[]\\~"), "nick{}|~");
+        assert!(!strict.eq("a~", "a^"));
+    }
+
+    #[test]
+    fn ascii_only_folds_letters() {
+        let ascii = CaseMapping::Ascii;
+        assert_eq!(ascii.normalize("Nick[]\\~"), "nick[]\\~");
+        assert!(ascii.eq("NICK", "nick"));
+        assert!(!ascii.eq("#foo[]", "#foo{}"));
+        // Non ascii letters are never folded
+        assert!(!ascii.eq("Ä", "ä"));
+    }
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(CaseMapping::parse("RFC1459"), CaseMapping::Rfc1459);
+        assert_eq!(CaseMapping::parse("strict-rfc1459"), CaseMapping::StrictRfc1459);
+        assert_eq!(CaseMapping::parse("ascii"), CaseMapping::Ascii);
+        assert_eq!(CaseMapping::parse("rfc7613"), CaseMapping::Ascii);
+    }
+
+    #[test]
+    fn map_keeps_the_first_casing() {
+        let mut map: CaseMap<u32> = CaseMap::default();
+        map.insert("#Rust[]".to_string(), 1);
+        assert_eq!(map.insert("#RUST{}".to_string(), 2), Some(1));
+        assert_eq!(map.key("#rust{}").map(|k| k.as_str()), Some("#Rust[]"));
+        assert_eq!(map.get("#rust[]"), Some(&2));
+        assert_eq!(map.keys().count(), 1);
+        assert_eq!(map.remove("#RUST[]"), Some(2));
+        assert!(map.get("#Rust[]").is_none());
+    }
+
+    #[test]
+    fn map_rekeys_on_a_new_casemapping() {
+        let mut map: CaseMap<u32> = CaseMap::default();
+        map.set_casemapping(CaseMapping::Ascii);
+        map.insert("#a[]".to_string(), 1);
+        map.insert("#A{}".to_string(), 2);
+        assert_eq!(map.keys().count(), 2);
+        // Under rfc1459 they collide, the first one by the old key order is kept
+        map.set_casemapping(CaseMapping::Rfc1459);
+        assert_eq!(map.keys().count(), 1);
+        assert!(map.contains_key("#A[]"));
+    }
+}
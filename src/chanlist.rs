@@ -0,0 +1,267 @@
+// chanlist.rs
+use crate::app::{App, Popup};
+use crate::message::IrcMessage;
+
+// One channel from a 322 reply or an ALIS notice
+#[derive(Debug, Clone, Default)]
+pub struct ListEntry {
+    pub channel: String,
+    pub users: usize,
+    pub topic: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListSort {
+    #[default]
+    Users,
+    Name,
+}
+
+// State of the /list popup, the replies of one server
+#[derive(Default)]
+pub struct ChannelList {
+    pub server: String,
+    pub entries: Vec<ListEntry>,
+    // Between 321 (or the /list command) and 323
+    pub loading: bool,
+    // Typed while the popup is open, matches the channel name or topic
+    pub filter: String,
+    pub sort: ListSort,
+    // Indexes into entries, filtered and sorted
+    pub shown: Vec<usize>,
+    pub selected: usize,
+    pub offset: usize,
+    dirty: bool,
+    // Esc while loading, the replies still come in but the popup stays shut
+    dismissed: bool,
+    // /alis was sent and ALIS has not answered yet
+    alis_pending: bool,
+    // Between the ALIS header and footer, only notices in there are channels
+    alis_open: bool,
+}
+
+impl ChannelList {
+    pub fn add(&mut self, entry: ListEntry) {
+        self.entries.push(entry);
+        self.dirty = true;
+    }
+
+    // Filters and sorts again after a change, once per frame at most
+    pub fn refresh(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let filter = self.filter.to_lowercase();
+        let mut shown: Vec<usize> = self.entries.iter().enumerate()
+            .filter(|(_, e)| filter.is_empty() || e.channel.to_lowercase().contains(&filter) || e.topic.to_lowercase().contains(&filter))
+            .map(|(i, _)| i)
+            .collect();
+        match self.sort {
+            ListSort::Users => shown.sort_by(|a, b| self.entries[*b].users.cmp(&self.entries[*a].users)),
+            ListSort::Name => shown.sort_by_cached_key(|i| self.entries[*i].channel.to_lowercase()),
+        }
+        self.shown = shown;
+        self.selected = self.selected.min(self.shown.len().saturating_sub(1));
+    }
+
+    pub fn selected_entry(&mut self) -> Option<&ListEntry> {
+        self.refresh();
+        self.shown.get(self.selected).map(|i| &self.entries[*i])
+    }
+}
+
+// The /list or /alis command went out, the popup opens empty and loading
+pub fn start(app: &mut App) {
+    app.chanlist = ChannelList { server: app.active_server.clone(), loading: true, ..Default::default() };
+    app.popup = Popup::List;
+}
+
+pub fn start_alis(app: &mut App) {
+    start(app);
+    app.chanlist.alis_pending = true;
+}
+
+// 321, servers that send it start a fresh list
+pub fn list_start(app: &mut App, server_id: &str) {
+    if app.chanlist.server != server_id || !app.chanlist.loading {
+        app.chanlist = ChannelList { server: server_id.to_string(), ..Default::default() };
+    }
+    app.chanlist.loading = true;
+    app.chanlist.dirty = true;
+    if !app.chanlist.dismissed {
+        app.popup = Popup::List;
+    }
+}
+
+// A reply that is not part of the list being loaded starts a new one
+fn ensure_started(app: &mut App, server_id: &str) {
+    if app.chanlist.server != server_id || !app.chanlist.loading {
+        list_start(app, server_id);
+    }
+}
+
+// 322: own nick, channel, user count, topic
+pub fn reply(app: &mut App, server_id: &str, msg: &IrcMessage) {
+    ensure_started(app, server_id);
+    app.chanlist.add(ListEntry {
+        channel: msg.param(1).to_string(),
+        users: msg.param(2).parse().unwrap_or(0),
+        topic: msg.param(3).to_string(),
+    });
+}
+
+// 323 or the end of the ALIS output
+pub fn list_end(app: &mut App) {
+    app.chanlist.loading = false;
+    app.chanlist.dirty = true;
+}
+
+// ALIS answers in notices, "#channel  42 :topic" between a header and a footer line.
+// False for anything else, errors and help text, which are shown as they are
+pub fn alis_notice(app: &mut App, server_id: &str, text: &str) -> bool {
+    let upper = text.to_uppercase();
+    if upper.contains("RETURNING MAXIMUM OF") {
+        list_start(app, server_id);
+        app.chanlist.alis_pending = false;
+        app.chanlist.alis_open = true;
+        return true;
+    }
+    let list = &app.chanlist;
+    if !list.alis_open || list.server != server_id {
+        // ALIS answered the pending /alis with something that is not a list
+        if list.alis_pending && list.server == server_id {
+            app.chanlist.alis_pending = false;
+            list_end(app);
+        }
+        return false;
+    }
+    if upper.contains("MAXIMUM CHANNEL OUTPUT REACHED") || upper.contains("END OF OUTPUT") {
+        app.chanlist.alis_open = false;
+        list_end(app);
+        return true;
+    }
+    match parse_alis(text) {
+        Some(entry) => {
+            app.chanlist.add(entry);
+            true
+        }
+        None => false,
+    }
+}
+
+fn parse_alis(text: &str) -> Option<ListEntry> {
+    let text = text.trim_start();
+    if !text.starts_with('#') {
+        return None;
+    }
+    let (channel, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let rest = rest.trim_start();
+    let (users, topic) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let topic = topic.trim_start();
+    Some(ListEntry {
+        channel: channel.to_string(),
+        users: users.parse().unwrap_or(0),
+        topic: topic.strip_prefix(':').unwrap_or(topic).to_string(),
+    })
+}
+
+// Esc keeps what was loaded so far, only the popup goes away until the next /list
+pub fn close(app: &mut App) {
+    app.chanlist.dismissed = true;
+}
+
+pub fn move_selection(app: &mut App, step: isize) {
+    let list = &mut app.chanlist;
+    list.refresh();
+    let last = list.shown.len().saturating_sub(1);
+    list.selected = list.selected.saturating_add_signed(step).min(last);
+}
+
+pub fn type_char(app: &mut App, c: char) {
+    app.chanlist.filter.push(c);
+    app.chanlist.selected = 0;
+    app.chanlist.dirty = true;
+}
+
+pub fn backspace(app: &mut App) {
+    app.chanlist.filter.pop();
+    app.chanlist.selected = 0;
+    app.chanlist.dirty = true;
+}
+
+// Alt + s switches between most users first and by name
+pub fn toggle_sort(app: &mut App) {
+    app.chanlist.sort = match app.chanlist.sort {
+        ListSort::Users => ListSort::Name,
+        ListSort::Name => ListSort::Users,
+    };
+    app.chanlist.selected = 0;
+    app.chanlist.dirty = true;
+}
+
+// Joins the selected channel on the server the list came from
+pub fn enter(app: &mut App) {
+    let channel = app.chanlist.selected_entry().map(|e| e.channel.clone());
+    let server_id = app.chanlist.server.clone();
+    if let Some(channel) = channel {
+        if app.server_list.contains_key(&server_id) {
+            app.stream_mgr.send_line(server_id, IrcMessage::new("JOIN", &[&channel]).to_string());
+        }
+    }
+    close(app);
+    app.popup = Popup::None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alis_app() -> App {
+        let mut app = App::default();
+        app.active_server = "irc.libera.chat".to_string();
+        start_alis(&mut app);
+        app
+    }
+
+    #[test]
+    fn alis_entries_between_header_and_footer() {
+        let mut app = alis_app();
+        let server = "irc.libera.chat";
+        assert!(alis_notice(&mut app, server, "Returning maximum of 60 channel names matching '*rust*'"));
+        assert!(alis_notice(&mut app, server, "#rust                 1234 :Rust programming, see #rust-beginners"));
+        assert!(alis_notice(&mut app, server, "#rust-offtopic          87 :"));
+        assert!(app.chanlist.loading);
+        assert!(alis_notice(&mut app, server, "End of output"));
+        assert!(!app.chanlist.loading);
+
+        assert_eq!(app.chanlist.entries.len(), 2);
+        let first = &app.chanlist.entries[0];
+        assert_eq!((first.channel.as_str(), first.users), ("#rust", 1234));
+        assert_eq!(first.topic, "Rust programming, see #rust-beginners");
+        assert_eq!(app.chanlist.entries[1].topic, "");
+    }
+
+    #[test]
+    fn alis_notices_outside_the_list_are_not_entries() {
+        let mut app = alis_app();
+        let server = "irc.libera.chat";
+        // An error instead of a header ends the loading and is shown as a notice
+        assert!(!alis_notice(&mut app, server, "Invalid parameter: see /msg ALIS HELP, or #libera for help"));
+        assert!(!app.chanlist.loading);
+        assert!(app.chanlist.entries.is_empty());
+
+        assert!(alis_notice(&mut app, server, "Returning maximum of 60 channel names matching '*'"));
+        assert!(alis_notice(&mut app, server, "Maximum channel output reached"));
+        assert!(!alis_notice(&mut app, server, "#late  5 :after the footer"));
+        assert!(app.chanlist.entries.is_empty());
+    }
+
+    #[test]
+    fn alis_from_another_server_is_ignored() {
+        let mut app = alis_app();
+        assert!(alis_notice(&mut app, "irc.libera.chat", "Returning maximum of 60 channel names matching '*'"));
+        assert!(!alis_notice(&mut app, "irc.other.net", "#elsewhere  3 :topic"));
+        assert!(app.chanlist.entries.is_empty());
+    }
+}
@@ -0,0 +1,788 @@
+// command.rs
+use crate::app::{App, Popup, ServerData, ChannelData, ChannelMap, ChatLine};
+use crate::config;
+use crate::search;
+use crate::chanlist;
+use crate::layout::{self, Dir, Pane, Side, WindowLayout};
+use crate::ctcp;
+use crate::message::IrcMessage;
+use crate::stream::{ServerId, NetEvent, Identity};
+use tokio::sync::mpsc::UnboundedSender;
+use std::collections::btree_map::Entry;
+use std::future::Future;
+use std::pin::Pin;
+
+type NetSender = UnboundedSender<(ServerId, NetEvent)>;
+type CommandFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+pub enum Handler {
+    Sync(fn(&mut App, &[String])),
+    // Commands that open connections have to await the stream manager
+    Async(for<'a> fn(&'a mut App, Vec<String>, &'a NetSender) -> CommandFuture<'a>),
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    // Argument spec shown in usage errors and the help popup
+    pub args: &'static str,
+    pub min_args: usize,
+    pub max_args: usize,
+    // The last argument takes the rest of the line, spaces included
+    pub rest: bool,
+    pub help: &'static str,
+    pub handler: Handler,
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command { name: "quit", aliases: &["exit"], args: "", min_args: 0, max_args: 0, rest: false,
+        help: "To quit the application", handler: Handler::Async(quit) },
+    Command { name: "connect", aliases: &[], args: "[-tls|-insecure] server[:port]", min_args: 1, max_args: 3, rest: false,
+        help: "To connect to a server", handler: Handler::Async(connect) },
+    Command { name: "twitch_connect", aliases: &[], args: "", min_args: 0, max_args: 0, rest: false,
+        help: "Join twitch, uses nick and oauth from the config", handler: Handler::Async(twitch_connect) },
+    Command { name: "load", aliases: &[], args: "", min_args: 0, max_args: 0, rest: false,
+        help: "Connect to the autojoin servers in the config", handler: Handler::Async(load) },
+    Command { name: "disconnect", aliases: &[], args: "server", min_args: 1, max_args: 1, rest: false,
+        help: "To disconnect from a server", handler: Handler::Sync(disconnect) },
+    Command { name: "join", aliases: &[], args: "channel [key]", min_args: 1, max_args: 2, rest: false,
+        help: "To join a chat channel", handler: Handler::Sync(join) },
+    Command { name: "part", aliases: &["leave"], args: "[#channel] [reason]", min_args: 0, max_args: 2, rest: true,
+        help: "To leave a chat channel, the active one if none given", handler: Handler::Sync(part) },
+    Command { name: "nick", aliases: &[], args: "nickname", min_args: 1, max_args: 1, rest: false,
+        help: "To change nick on the active server", handler: Handler::Sync(nick) },
+    Command { name: "msg", aliases: &[], args: "nick message", min_args: 2, max_args: 2, rest: true,
+        help: "Private message, opens a buffer for the nick", handler: Handler::Sync(msg) },
+    Command { name: "me", aliases: &[], args: "action", min_args: 1, max_args: 1, rest: true,
+        help: "Send an action to the active channel, shown as * nick action", handler: Handler::Sync(me) },
+    Command { name: "ctcp", aliases: &[], args: "nick VERB [args]", min_args: 2, max_args: 3, rest: true,
+        help: "Send a CTCP request, PING without args measures the lag", handler: Handler::Sync(ctcp) },
+    Command { name: "topic", aliases: &[], args: "[new topic]", min_args: 0, max_args: 1, rest: true,
+        help: "Show the topic of the active channel, or set it", handler: Handler::Sync(topic) },
+    Command { name: "away", aliases: &[], args: "[message]", min_args: 0, max_args: 1, rest: true,
+        help: "Mark yourself away on the active server, without a message you are back", handler: Handler::Sync(away) },
+    Command { name: "whois", aliases: &[], args: "nick", min_args: 1, max_args: 1, rest: false,
+        help: "Show who a nick is in a popup", handler: Handler::Sync(whois) },
+    Command { name: "whowas", aliases: &[], args: "nick", min_args: 1, max_args: 1, rest: false,
+        help: "Show who a nick that left was", handler: Handler::Sync(whowas) },
+    Command { name: "ignore", aliases: &[], args: "[nick]", min_args: 0, max_args: 1, rest: false,
+        help: "Hide messages, notices and invites from a nick, without a nick lists the ignored", handler: Handler::Sync(ignore) },
+    Command { name: "unignore", aliases: &[], args: "nick", min_args: 1, max_args: 1, rest: false,
+        help: "Stop ignoring a nick", handler: Handler::Sync(unignore) },
+    Command { name: "list", aliases: &[], args: "[filter]", min_args: 0, max_args: 1, rest: true,
+        help: "List channels, type to filter, alt + s sorts, Enter joins, Esc closes", handler: Handler::Sync(list) },
+    Command { name: "alis", aliases: &[], args: "[pattern [options]] (Libera.Chat)", min_args: 0, max_args: 1, rest: true,
+        help: "List channels through ALIS, same keys as /list", handler: Handler::Sync(alis) },
+    Command { name: "search", aliases: &["find"], args: "regex", min_args: 1, max_args: 1, rest: true,
+        help: "Search all buffers, Enter on a result to go there", handler: Handler::Sync(search) },
+    Command { name: "swap", aliases: &[], args: "number", min_args: 1, max_args: 1, rest: false,
+        help: "Swap active channel", handler: Handler::Sync(swap) },
+    Command { name: "split", aliases: &[], args: "number-number", min_args: 1, max_args: 1, rest: false,
+        help: "Split screen view", handler: Handler::Sync(split) },
+    Command { name: "window", aliases: &[], args: "split h|v [number] | close | move left|right|up|down", min_args: 1, max_args: 3, rest: false,
+        help: "Split the focused pane (h stacks, v side by side), close it, or move its buffer", handler: Handler::Sync(window) },
+    Command { name: "alias", aliases: &[], args: "[name] [/command; /command]", min_args: 0, max_args: 2, rest: true,
+        help: "Add an alias, $1 $2- $nick $chan $server are filled in, without arguments lists them", handler: Handler::Sync(alias) },
+    Command { name: "unalias", aliases: &[], args: "name", min_args: 1, max_args: 1, rest: false,
+        help: "Remove an alias", handler: Handler::Sync(unalias) },
+    Command { name: "help", aliases: &[], args: "", min_args: 0, max_args: 0, rest: false,
+        help: "Show this help", handler: Handler::Sync(help) },
+];
+
+// Exact, case insensitive match on the name or an alias
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name) || c.aliases.iter().any(|a| a.eq_ignore_ascii_case(name)))
+}
+
+pub fn usage(app: &mut App, command: &Command) {
+    let text = format!("Usage: /{} {}", command.name, command.args);
+    app.chat_bounds(text.trim_end().to_string(), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+}
+
+// Splits the argument string, None if there are more arguments than the command takes
+fn parse_args(mut rest: &str, command: &Command) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Some(args);
+        }
+        if args.len() == command.max_args {
+            return None;
+        }
+        if command.rest && args.len() + 1 == command.max_args {
+            args.push(rest.trim_end().to_string());
+            return Some(args);
+        }
+        let (arg, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        args.push(arg.to_string());
+        rest = tail;
+    }
+}
+
+// Backstop for long chains of different aliases, loops are caught by name before this
+const MAX_ALIAS_DEPTH: usize = 10;
+
+// Resolves to false once an alias loop or the depth limit stopped the expansion
+type AliasFuture<'a> = Pin<Box<dyn Future<Output = bool> + 'a>>;
+
+// Everything typed in the prompt goes through here, alias expansions included
+pub async fn run_line(app: &mut App, line: String, net_tx: &NetSender) {
+    run_expanded(app, line, net_tx, &mut Vec::new()).await;
+}
+
+// chain holds the aliases being expanded right now, outermost first
+fn run_expanded<'a>(app: &'a mut App, line: String, net_tx: &'a NetSender, chain: &'a mut Vec<String>) -> AliasFuture<'a> {
+    Box::pin(async move {
+        if !line.starts_with('/') {
+            if !line.is_empty() {
+                say(app, &line);
+            }
+            return true;
+        }
+        let body = &line[1..];
+        let (name, rest) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        let name = name.to_lowercase();
+        let expansion = match app.aliases.get(&name) {
+            Some(e) => e.clone(),
+            None => {
+                dispatch(app, &line, net_tx).await;
+                return true;
+            }
+        };
+        if chain.contains(&name) {
+            let path: Vec<String> = chain.iter().chain(std::iter::once(&name)).map(|a| format!("/{}", a)).collect();
+            app.chat_bounds(format!("Alias loop: {}", path.join(" → ")), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+            return false;
+        }
+        if chain.len() >= MAX_ALIAS_DEPTH {
+            app.chat_bounds(format!("Alias /{} nests more than {} aliases deep", name, MAX_ALIAS_DEPTH), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+            return false;
+        }
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        chain.push(name.clone());
+        for template in expansion {
+            let expanded = expand_alias(app, &template, &name, &args);
+            if !run_expanded(app, expanded, net_tx, chain).await {
+                return false;
+            }
+        }
+        chain.pop();
+        true
+    })
+}
+
+// $0 the alias name, $1..$9 single arguments, $2- from the second to the end, $nick $chan $server from the active buffer
+pub fn expand_alias(app: &App, template: &str, name: &str, args: &[&str]) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        if let Some(digit) = chars.peek().and_then(|d| d.to_digit(10)) {
+            chars.next();
+            if digit == 0 {
+                out.push_str(name);
+                continue;
+            }
+            let index = (digit as usize).saturating_sub(1);
+            if chars.peek() == Some(&'-') {
+                chars.next();
+                out.push_str(&args.get(index..).unwrap_or(&[]).join(" "));
+            } else {
+                out.push_str(args.get(index).copied().unwrap_or(""));
+            }
+            continue;
+        }
+        let mut var = String::new();
+        while let Some(&n) = chars.peek() {
+            if !n.is_ascii_alphabetic() {
+                break;
+            }
+            var.push(n);
+            chars.next();
+        }
+        match var.as_str() {
+            "nick" => out.push_str(&app.active_nick),
+            "chan" => out.push_str(&app.active_channel),
+            "server" => out.push_str(&app.active_server),
+            _ => {
+                out.push('$');
+                out.push_str(&var);
+            }
+        }
+    }
+    out
+}
+
+// A prompt line starting with '/', unknown commands are sent to the server as they are
+pub async fn dispatch(app: &mut App, line: &str, net_tx: &NetSender) {
+    let body = &line[1..];
+    let (name, rest) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+    let command = match find(name) {
+        Some(c) => c,
+        None => {
+            //Raw command, parsed so a malformed line never reaches the server
+            if let Some(prompt_command) = IrcMessage::parse(body) {
+                app.stream_mgr.send_line(app.active_server.clone(), prompt_command.to_string());
+            }
+            return;
+        }
+    };
+    let args = match parse_args(rest, command) {
+        Some(a) if a.len() >= command.min_args => a,
+        _ => return usage(app, command),
+    };
+    match command.handler {
+        Handler::Sync(f) => f(app, &args),
+        Handler::Async(f) => f(app, args, net_tx).await,
+    }
+}
+
+// Turns "\u{1F600}" written in the prompt into the character
+fn unescape_unicode(text: &str) -> String {
+    let mut result = text.to_string();
+    while let Some(start) = result.find("\\u{") {
+        if let Some(end) = result[start..].find('}') {
+            let end = start + end + 1;
+            let unicode_escape = &result[start..end];
+            let hex_value = &unicode_escape[3..unicode_escape.len() - 1];
+
+            match u32::from_str_radix(hex_value, 16).ok().and_then(char::from_u32) {
+                Some(character) => {
+                    result = format!("{}{}{}", &result[..start], character, &result[end..]);
+                }
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    result
+}
+
+// A prompt line that is not a command, sent to the active channel
+pub fn say(app: &mut App, line: &str) {
+    if app.active_server != "System" {
+        let result = unescape_unicode(line);
+        let prompt_write = IrcMessage::new("PRIVMSG", &[&app.active_channel, &result]);
+        app.stream_mgr.send_line(app.active_server.clone(), prompt_write.to_string());
+        if !app.server_list.get(&app.active_server).is_some_and(|s| s.has_cap("echo-message")) {
+            app.chat_bounds(result.clone(), app.active_server.clone(), app.active_channel.clone(), app.active_nick.clone())
+        }
+    } else {
+        app.chat_bounds("Error currently not connected to a server or in a channel".to_owned(), "System".to_owned(), "Status".to_owned(), "Error".to_string());
+    }
+}
+
+// Adds the server with its Status buffer and makes it active
+fn add_server(app: &mut App, server_id: &str, nick: &str, addr: &str) {
+    match app.server_list.entry(server_id.to_string()) {
+        Entry::Occupied(o) => o.into_mut(),
+        Entry::Vacant(v) => {
+        // Create a new HashMap with the "Status" channel already inserted
+            let mut channels = ChannelMap::default();
+            channels.insert("Status".to_string(), ChannelData::default());
+            v.insert(ServerData {
+                channels,
+                nick: nick.to_string(),
+                ..Default::default()
+            })
+        }
+    };
+    app.active_server = server_id.to_string();
+    app.active_channel = "Status".to_string();
+    app.active_nick = nick.to_string();
+    if let Some(server) = app.server_list.get_mut(server_id) {
+        if let Some(channel) = server.channels.get_mut("Status") {
+            channel.chat_list.push(ChatLine::new("System", &format!("<connecting to {}>", addr)));
+        }
+    }
+}
+
+fn quit<'a>(app: &'a mut App, _args: Vec<String>, _net_tx: &'a NetSender) -> CommandFuture<'a> {
+    Box::pin(async move {
+        app.quit().await;
+    })
+}
+
+fn connect<'a>(app: &'a mut App, args: Vec<String>, net_tx: &'a NetSender) -> CommandFuture<'a> {
+    Box::pin(async move {
+        let (flags, host): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with('-'));
+        let host = match host.as_slice() {
+            [host] => host.as_str(),
+            _ => return usage(app, find("connect").unwrap()),
+        };
+        let (server_id, port) = match host.split_once(':') {
+            Some((s, p)) if p.parse::<u16>().is_ok() => (s.to_string(), Some(p)),
+            Some(_) => return usage(app, find("connect").unwrap()),
+            None => (host.to_string(), None),
+        };
+        //Config settings for the server first, then flags on top
+        let mut opts = config::server_options(&server_id);
+        for flag in flags {
+            match flag.to_lowercase().as_str() {
+                "-tls" => opts.tls = true,
+                "-insecure" => {
+                    opts.tls = true;
+                    opts.tls_insecure = true;
+                }
+                _ => return usage(app, find("connect").unwrap()),
+            }
+        }
+        let port = port.unwrap_or(if opts.tls { "6697" } else { "6667" });
+        let addr = format!("{}:{}", server_id, port);
+
+        let nick = app.active_nick.clone();
+        opts.identity = Identity::new(&nick, &app.real);
+        app.stream_mgr.connect(server_id.clone(), addr.clone(), net_tx.clone(), opts);
+        add_server(app, &server_id, &nick, &addr);
+    })
+}
+
+fn twitch_connect<'a>(app: &'a mut App, _args: Vec<String>, net_tx: &'a NetSender) -> CommandFuture<'a> {
+    Box::pin(async move {
+        let tw_serv = "irc.chat.twitch.tv";
+        let tw_port = ":6667";
+        let (tw_nick, oauth) = config::read_twitch();
+        if oauth == "Error" {
+            app.chat_bounds(tw_nick.to_owned(), app.active_server.clone(), app.active_channel.clone(), "ERROR".to_string());
+        } else {
+            let mut opts = config::twitch_options(tw_serv);
+            opts.identity = Identity { oauth, ..Identity::new(&tw_nick, &app.real) };
+            app.stream_mgr.connect(tw_serv.to_string(), tw_serv.to_owned() + tw_port, net_tx.clone(), opts);
+            add_server(app, tw_serv, &tw_nick, &(tw_serv.to_owned() + tw_port));
+        }
+    })
+}
+
+fn load<'a>(app: &'a mut App, _args: Vec<String>, net_tx: &'a NetSender) -> CommandFuture<'a> {
+    Box::pin(config::read_autojoin(app, net_tx))
+}
+
+fn disconnect(app: &mut App, args: &[String]) {
+    let server_id = args[0].as_str();
+    if app.server_list.contains_key(server_id) {
+        app.stream_mgr.disconnect(server_id);
+        app.server_list.remove(server_id);
+        app.buffer_closed(server_id, None);
+    } else {
+        app.chat_bounds(format!("Not connected to {}", server_id), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+    }
+}
+
+fn join(app: &mut App, args: &[String]) {
+    if app.active_server!= "System" {
+        // "/join rust" is "/join #rust", names already starting with a channel type are kept
+        let channels: Vec<String> = args[0].split(',').map(|c| {
+            if c == "0" || app.is_channel(&app.active_server, c) { c.to_string() } else { format!("#{}", c) }
+        }).collect();
+        let channels = channels.join(",");
+        let mut params = vec![channels.as_str()];
+        params.extend(args.get(1).map(|k| k.as_str()));
+        app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("JOIN", &params).to_string());
+    } else {
+        app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "ERROR".to_string());
+    }
+}
+
+fn part(app: &mut App, args: &[String]) {
+    // Without arguments the active buffer is left
+    let part_chan = args.first().cloned().unwrap_or(app.active_channel.clone());
+    // The buffer name as shown, "/part #RUST" closes #rust
+    let part_chan = app.server_list.get(&app.active_server).and_then(|s| s.channels.key(&part_chan)).cloned().unwrap_or(part_chan);
+    let reason = args.get(1);
+    if app.is_channel(&app.active_server, &part_chan) {
+        let part = match reason {
+            Some(r) => IrcMessage::new("PART", &[&part_chan, r]),
+            None => IrcMessage::new("PART", &[&part_chan]),
+        };
+        app.stream_mgr.send_line(app.active_server.clone(), part.to_string());
+    }
+    if let Some(server) = app.server_list.get_mut(&app.active_server) {
+        if server.channels.contains_key(&part_chan) && part_chan != "Status" {
+            server.channels.remove(&part_chan);
+
+            let server_id = app.active_server.clone();
+            app.buffer_closed(&server_id, Some(&part_chan));
+        } else {
+            app.chat_bounds("Channel Not Joined".to_string(), app.active_server.clone(), app.active_channel.clone(), "Error".to_string())
+        }
+    }
+}
+
+fn nick(app: &mut App, args: &[String]) {
+    let new_nick = args[0].as_str();
+    if app.active_server != "System" {
+        app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("NICK", &[new_nick]).to_string());
+    } else {
+        app.active_nick = new_nick.to_string();
+        app.chat_bounds("You're now known as ".to_owned() + &app.active_nick, "System".to_string(), "Status".to_string(), "NICK".to_string())
+    }
+}
+
+fn msg(app: &mut App, args: &[String]) {
+    if app.active_server != "System" {
+        let nick = args[0].as_str();
+        let msg = unescape_unicode(&args[1]);
+
+        let prompt_write = IrcMessage::new("PRIVMSG", &[nick, &msg]);
+        app.stream_mgr.send_line(app.active_server.clone(), prompt_write.to_string());
+        app.chat_bounds(msg.clone(), app.active_server.clone(), app.active_channel.clone(), app.active_nick.clone());
+        //With echo-message the server sends it back to the query buffer
+        if !app.server_list.get(&app.active_server).is_some_and(|s| s.has_cap("echo-message")) {
+            app.chat_bounds(msg.clone(), app.active_server.clone(), nick.to_string(), app.active_nick.clone());
+        }
+    } else {
+        app.chat_bounds("Error Not Connected, or wrong server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string())
+    }
+}
+
+fn me(app: &mut App, args: &[String]) {
+    if app.active_server != "System" && app.active_channel != "Status" {
+        let action = unescape_unicode(&args[0]);
+        let prompt_write = IrcMessage::new("PRIVMSG", &[&app.active_channel, &ctcp::encode("ACTION", &action)]);
+        app.stream_mgr.send_line(app.active_server.clone(), prompt_write.to_string());
+        if !app.server_list.get(&app.active_server).is_some_and(|s| s.has_cap("echo-message")) {
+            app.chat_action(action, app.active_server.clone(), app.active_channel.clone(), app.active_nick.clone());
+        }
+    } else {
+        app.chat_bounds("Error not in a channel or query".to_owned(), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+    }
+}
+
+fn ctcp(app: &mut App, args: &[String]) {
+    if app.active_server == "System" {
+        return app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string());
+    }
+    let nick = args[0].as_str();
+    let verb = args[1].to_ascii_uppercase();
+    let params = match args.get(2) {
+        Some(p) => p.clone(),
+        None if verb == "PING" => ctcp::ping_stamp(),
+        None => String::new(),
+    };
+    let request = IrcMessage::new("PRIVMSG", &[nick, &ctcp::encode(&verb, &params)]);
+    app.stream_mgr.send_line(app.active_server.clone(), request.to_string());
+    app.chat_bounds(format!("CTCP {} sent to {}", verb, nick), app.active_server.clone(), "Status".to_string(), "CTCP".to_string());
+}
+
+fn topic(app: &mut App, args: &[String]) {
+    let chan = app.active_channel.clone();
+    if !app.is_channel(&app.active_server, &chan) {
+        return app.chat_bounds("Error not in a channel".to_owned(), app.active_server.clone(), app.active_channel.clone(), "Error".to_string());
+    }
+    let server = match app.server_list.get(&app.active_server) {
+        Some(s) => s,
+        None => return,
+    };
+    match args.first() {
+        Some(text) => {
+            if let Some(max) = server.isupport.topiclen.filter(|&max| text.len() > max) {
+                let warning = format!("Topic is longer than {} bytes, the server may cut it", max);
+                app.chat_bounds(warning, app.active_server.clone(), chan.clone(), "TOPIC".to_string());
+            }
+            app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("TOPIC", &[&chan, text]).to_string());
+        }
+        None => {
+            match server.channels.get(&chan).and_then(|c| c.topic.clone()) {
+                Some(topic) => {
+                    let set_by = match (topic.setter, topic.time) {
+                        (Some(setter), Some(time)) => format!(" (set by {} on {})", setter, time.format("%Y-%m-%d %H:%M")),
+                        (Some(setter), None) => format!(" (set by {})", setter),
+                        _ => String::new(),
+                    };
+                    app.chat_bounds(format!("Topic: {}{}", topic.text, set_by), app.active_server.clone(), chan, "TOPIC".to_string());
+                }
+                // Not known yet, the server answers with 332 or 331
+                None => app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("TOPIC", &[&chan]).to_string()),
+            }
+        }
+    }
+}
+
+fn away(app: &mut App, args: &[String]) {
+    if app.active_server == "System" {
+        return app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string());
+    }
+    // The server answers with 306 or 305, the away flag is set from those
+    let away = match args.first() {
+        Some(message) => IrcMessage::new("AWAY", &[message]),
+        None => IrcMessage::new("AWAY", &[]),
+    };
+    app.stream_mgr.send_line(app.active_server.clone(), away.to_string());
+}
+
+// Commands the server handles, registered for help and completion
+fn send_raw(app: &mut App, command: &str, args: &[String]) {
+    let line = format!("{} {}", command, args.join(" "));
+    if let Some(prompt_command) = IrcMessage::parse(line.trim_end()) {
+        app.stream_mgr.send_line(app.active_server.clone(), prompt_command.to_string());
+    }
+}
+
+fn whois(app: &mut App, args: &[String]) {
+    send_raw(app, "WHOIS", args);
+}
+
+fn whowas(app: &mut App, args: &[String]) {
+    send_raw(app, "WHOWAS", args);
+}
+
+fn ignore(app: &mut App, args: &[String]) {
+    match args.first() {
+        Some(nick) => app.ignore(nick),
+        None => {
+            let text = if app.ignores.is_empty() { "Ignoring nobody".to_string() } else { format!("Ignoring {}", app.ignores.join(", ")) };
+            app.chat_bounds(text, app.active_server.clone(), app.active_channel.clone(), "System".to_string());
+        }
+    }
+}
+
+fn unignore(app: &mut App, args: &[String]) {
+    let nick = args[0].as_str();
+    let casemap = app.server_list.get(&app.active_server).map(|s| s.casemap()).unwrap_or_default();
+    let before = app.ignores.len();
+    app.ignores.retain(|i| !casemap.eq(i, nick));
+    let text = if app.ignores.len() < before { format!("No longer ignoring {}", nick) } else { format!("{} is not ignored", nick) };
+    app.chat_bounds(text, app.active_server.clone(), app.active_channel.clone(), "System".to_string());
+}
+
+fn list(app: &mut App, args: &[String]) {
+    if app.active_server == "System" {
+        return app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string());
+    }
+    chanlist::start(app);
+    send_raw(app, "LIST", args);
+}
+
+fn alis(app: &mut App, args: &[String]) {
+    if app.active_server == "System" {
+        return app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string());
+    }
+    chanlist::start_alis(app);
+    // ALIS is a service, it takes "LIST <pattern> [options]" in a private message
+    let pattern = args.first().map(|a| a.as_str()).unwrap_or("*");
+    let text = format!("LIST {}", pattern);
+    app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("PRIVMSG", &["ALIS", &text]).to_string());
+}
+
+fn search(app: &mut App, args: &[String]) {
+    let query = args[0].as_str();
+    match search::run(app, query) {
+        Ok(0) => app.chat_bounds(format!("No matches for {}", query), app.active_server.clone(), app.active_channel.clone(), "System".to_string()),
+        Ok(_) => app.popup = Popup::Search,
+        Err(e) => app.chat_bounds(format!("Invalid search: {}", e), app.active_server.clone(), app.active_channel.clone(), "Error".to_string()),
+    }
+}
+
+// (server, channel) in the order of the channel overview and the sidebar
+pub fn buffer_list(app: &App) -> Vec<(String, String)> {
+    let mut buffers = Vec::new();
+    for (outer_key, inner_map) in &app.server_list {
+        for inner_key in inner_map.channels.keys() {
+            buffers.push((outer_key.clone(), inner_key.clone()));
+        }
+    }
+    buffers
+}
+
+fn swap(app: &mut App, args: &[String]) {
+    let buffers = buffer_list(app);
+    let (server_id, channel_id) = match args[0].parse::<usize>().ok().and_then(|nr| buffers.get(nr)) {
+        Some(b) => b.clone(),
+        None => return usage(app, find("swap").unwrap()),
+    };
+    app.switch_buffer(&server_id, &channel_id);
+}
+
+fn split(app: &mut App, args: &[String]) {
+    let buffers = buffer_list(app);
+    let pair = args[0].split_once('-').and_then(|(l, r)| Some((l.trim().parse::<usize>().ok()?, r.trim().parse::<usize>().ok()?)));
+    let ((left_server, left), (right_server, right)) = match pair.and_then(|(l, r)| Some((buffers.get(l)?.clone(), buffers.get(r)?.clone()))) {
+        Some(p) => p,
+        None => return usage(app, find("split").unwrap()),
+    };
+    app.layout = WindowLayout::two(Pane::new(&left_server, &left), Pane::new(&right_server, &right));
+    app.switch_buffer(&left_server, &left);
+    if let Some(server) = app.server_list.get_mut(&right_server) {
+        if let Some(channel) = server.channels.get_mut(&right) {
+            channel.chat_pos = 0;
+            channel.mark_read();
+        }
+    }
+    layout::save(&app.layout);
+}
+
+// Window layout commands, the layout is saved right away
+fn window(app: &mut App, args: &[String]) {
+    app.layout.sync(&app.active_server, &app.active_channel);
+    match args[0].to_lowercase().as_str() {
+        "split" => {
+            let dir = match args.get(1).map(|d| d.to_lowercase()).as_deref() {
+                Some("h") => Dir::Horizontal,
+                Some("v") => Dir::Vertical,
+                _ => return usage(app, find("window").unwrap()),
+            };
+            // The new pane shows the same buffer unless a number from the overview is given
+            let pane = match args.get(2) {
+                Some(nr) => match nr.parse::<usize>().ok().and_then(|n| buffer_list(app).get(n).cloned()) {
+                    Some((server_id, channel_id)) => Pane::new(&server_id, &channel_id),
+                    None => return usage(app, find("window").unwrap()),
+                },
+                None => Pane::new(&app.active_server, &app.active_channel),
+            };
+            app.layout.split(dir, pane.clone());
+            app.switch_buffer(&pane.server, &pane.channel);
+        }
+        "close" => {
+            if !app.layout.close() {
+                return app.chat_bounds("Only one window is open".to_string(), app.active_server.clone(), app.active_channel.clone(), "Error".to_string());
+            }
+            let focus = app.layout.focus;
+            app.focus_pane(focus);
+        }
+        "move" => {
+            let side = match args.get(1).map(|d| d.to_lowercase()).as_deref() {
+                Some("left") => Side::Left,
+                Some("right") => Side::Right,
+                Some("up") => Side::Up,
+                Some("down") => Side::Down,
+                _ => return usage(app, find("window").unwrap()),
+            };
+            if !app.layout.move_pane(side) {
+                return app.chat_bounds(format!("No window {} of this one", args[1]), app.active_server.clone(), app.active_channel.clone(), "Error".to_string());
+            }
+        }
+        _ => return usage(app, find("window").unwrap()),
+    }
+    layout::save(&app.layout);
+}
+
+fn help(app: &mut App, _args: &[String]) {
+    app.popup = Popup::Help;
+    app.active_tab = 0;
+}
+
+fn alias(app: &mut App, args: &[String]) {
+    let status = |app: &mut App, text: String| app.chat_bounds(text, app.active_server.clone(), "Status".to_string(), "System".to_string());
+    match args {
+        [] => {
+            if app.aliases.is_empty() {
+                status(app, "No aliases defined".to_string());
+            }
+            for (name, expansion) in app.aliases.clone() {
+                status(app, format!("/{} = {}", name, expansion.join("; ")));
+            }
+        }
+        [name] => match app.aliases.get(&name.trim_start_matches('/').to_lowercase()) {
+            Some(expansion) => status(app, format!("/{} = {}", name.trim_start_matches('/'), expansion.join("; "))),
+            None => status(app, format!("No alias /{}", name.trim_start_matches('/'))),
+        },
+        [name, expansion] => {
+            let name = name.trim_start_matches('/').to_lowercase();
+            if find(&name).is_some() {
+                app.chat_bounds(format!("/{} is a built in command", name), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+                return;
+            }
+            let commands = split_alias(expansion);
+            status(app, format!("/{} = {}", name, commands.join("; ")));
+            app.aliases.insert(name, commands);
+        }
+        _ => {}
+    }
+}
+
+fn unalias(app: &mut App, args: &[String]) {
+    let name = args[0].trim_start_matches('/').to_lowercase();
+    if app.aliases.remove(&name).is_none() {
+        app.chat_bounds(format!("No alias /{}", name), app.active_server.clone(), "Status".to_string(), "Error".to_string());
+    }
+}
+
+// "/mode $chan +o $1; /msg $1 welcome" -> one command per entry, each starting with '/'
+pub fn split_alias(expansion: &str) -> Vec<String> {
+    expansion.split(';')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| if c.starts_with('/') { c.to_string() } else { format!("/{}", c) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    // Connected to "srv" as far as the commands can tell, nothing is sent anywhere
+    fn test_app() -> App {
+        let mut app = App::default();
+        let mut server = ServerData { nick: "me".to_string(), ..Default::default() };
+        server.channels.insert("Status".to_string(), ChannelData::default());
+        server.channels.insert("#c".to_string(), ChannelData::default());
+        app.server_list.insert("srv".to_string(), server);
+        app.active_server = "srv".to_string();
+        app.active_channel = "#c".to_string();
+        app.active_nick = "me".to_string();
+        app
+    }
+
+    fn buffer(app: &App, channel: &str) -> Vec<String> {
+        app.server_list["srv"].channels.get(channel).map(|c| c.chat_list.iter().map(|l| l.text.clone()).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn alias_substitution() {
+        let app = test_app();
+        let args = ["bob", "has", "joined"];
+        assert_eq!(expand_alias(&app, "/msg $1 hi", "greet", &args), "/msg bob hi");
+        assert_eq!(expand_alias(&app, "/msg $chan $2-", "greet", &args), "/msg #c has joined");
+        assert_eq!(expand_alias(&app, "$nick@$server via $0", "greet", &args), "me@srv via greet");
+        // Missing arguments are empty, unknown variables are kept
+        assert_eq!(expand_alias(&app, "[$5] [$4-] $other", "greet", &args), "[] [] $other");
+    }
+
+    #[test]
+    fn split_alias_lines() {
+        assert_eq!(split_alias("/mode $chan +o $1; msg $1 welcome ;"), vec!["/mode $chan +o $1", "/msg $1 welcome"]);
+    }
+
+    #[tokio::test]
+    async fn alias_runs_every_command() {
+        let mut app = test_app();
+        let (net_tx, _net_rx) = mpsc::unbounded_channel();
+        app.aliases.insert("greet".to_string(), vec!["/msg #d hello $1".to_string(), "/msg #d bye $1".to_string()]);
+        // Aliases that repeat another alias without recursing are fine
+        app.aliases.insert("twice".to_string(), vec!["/greet $1".to_string(), "/greet $1".to_string()]);
+        run_line(&mut app, "/TWICE bob".to_string(), &net_tx).await;
+        let lines = buffer(&app, "#d");
+        assert_eq!(lines.iter().filter(|l| *l == "hello bob").count(), 2);
+        assert_eq!(lines.iter().filter(|l| *l == "bye bob").count(), 2);
+        assert!(!buffer(&app, "Status").iter().any(|l| l.starts_with("Alias")));
+    }
+
+    #[tokio::test]
+    async fn alias_loop_stops_once() {
+        let mut app = test_app();
+        let (net_tx, _net_rx) = mpsc::unbounded_channel();
+        app.aliases.insert("a".to_string(), vec!["/b".to_string(), "/b".to_string()]);
+        app.aliases.insert("b".to_string(), vec!["/a".to_string(), "/a".to_string()]);
+        run_line(&mut app, "/a".to_string(), &net_tx).await;
+        let errors: Vec<String> = buffer(&app, "Status").into_iter().filter(|l| l.starts_with("Alias")).collect();
+        assert_eq!(errors, vec!["Alias loop: /a → /b → /a"]);
+    }
+
+    #[tokio::test]
+    async fn alias_depth_backstop() {
+        let mut app = test_app();
+        let (net_tx, _net_rx) = mpsc::unbounded_channel();
+        for i in 0..=MAX_ALIAS_DEPTH {
+            app.aliases.insert(format!("a{}", i), vec![format!("/a{}", i + 1)]);
+        }
+        run_line(&mut app, "/a0".to_string(), &net_tx).await;
+        let errors: Vec<String> = buffer(&app, "Status").into_iter().filter(|l| l.starts_with("Alias")).collect();
+        assert_eq!(errors, vec![format!("Alias /a{} nests more than {} aliases deep", MAX_ALIAS_DEPTH, MAX_ALIAS_DEPTH)]);
+    }
+}
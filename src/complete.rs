@@ -0,0 +1,84 @@
+// complete.rs
+use crate::app::App;
+use crate::cursor;
+use crate::textstyle;
+use crate::command;
+
+// Tab cycles through these until any other key is pressed
+pub struct Completion {
+    // Raw byte range in the prompt holding the current candidate
+    start: usize,
+    end: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+pub fn complete(app: &mut App) {
+    if let Some(mut completion) = app.completion.take() {
+        completion.index = (completion.index + 1) % completion.candidates.len();
+        let next = completion.candidates[completion.index].clone();
+        completion.end = replace(app, completion.start, completion.end, &next);
+        app.completion = Some(completion);
+        return;
+    }
+
+    let map = cursor::build_prompt_cursor_map(&app.prompt);
+    let end = cursor::byte_index(app.character_index, &map, &app.prompt);
+    // Word under the cursor, back to the previous space
+    let word_start = app.prompt[..end].rfind(' ').map(|i| i + 1).unwrap_or(0);
+    // Formatting codes in front of the word are kept, the completion goes after them
+    let start = map.visible_to_raw.iter().copied().find(|&raw| raw >= word_start && raw < end).unwrap_or(end);
+    let word = textstyle::strip_formatting(&app.prompt[start..end]);
+    let line_start = !map.visible_to_raw.iter().any(|&raw| raw < start);
+
+    let candidates = candidates(app, &word, line_start);
+    if candidates.is_empty() {
+        return;
+    }
+    let end = replace(app, start, end, &candidates[0]);
+    app.completion = Some(Completion { start, end, candidates, index: 0 });
+}
+
+fn candidates(app: &App, word: &str, line_start: bool) -> Vec<String> {
+    // Nicks and channels match the way the server compares them, the same as buffer lookups
+    let casemap = app.server_list.get(&app.active_server).map(|s| s.casemap()).unwrap_or_default();
+    let lower = casemap.normalize(word);
+    let mut found: Vec<String> = Vec::new();
+
+    if word.starts_with('/') && line_start {
+        let lower = word.to_lowercase();
+        found = command::COMMANDS.iter()
+            .flat_map(|c| std::iter::once(c.name).chain(c.aliases.iter().copied()))
+            .chain(app.aliases.keys().map(|a| a.as_str()))
+            .map(|name| format!("/{}", name))
+            .filter(|c| c.starts_with(&lower))
+            .map(|c| format!("{} ", c))
+            .collect();
+    } else if app.is_channel(&app.active_server, word) {
+        if let Some(server) = app.server_list.get(&app.active_server) {
+            found = server.channels.keys().filter(|c| casemap.normalize(c).starts_with(&lower)).map(|c| format!("{} ", c)).collect();
+        }
+    } else if !word.is_empty() {
+        if let Some(channel) = app.server_list.get(&app.active_server).and_then(|s| s.channels.get(&app.active_channel)) {
+            // Addressing someone at the start of the line gets "nick: "
+            let suffix = if line_start { ": " } else { " " };
+            found = channel.user_list.iter()
+                .map(|u| u.nick.as_str())
+                .filter(|u| casemap.normalize(u).starts_with(&lower))
+                .map(|u| format!("{}{}", u, suffix))
+                .collect();
+        }
+    }
+    found.sort_by_key(|c| casemap.normalize(c));
+    found.dedup();
+    found
+}
+
+// Puts text in place of the raw byte range and moves the cursor behind it, returns the new end
+fn replace(app: &mut App, start: usize, end: usize, text: &str) -> usize {
+    app.prompt.replace_range(start..end, text);
+    let new_end = start + text.len();
+    let map = cursor::build_prompt_cursor_map(&app.prompt);
+    app.character_index = map.visible_to_raw.iter().filter(|&&raw| raw < new_end).count();
+    new_end
+}
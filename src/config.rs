@@ -1,11 +1,18 @@
 use std::{collections::HashMap, fs, path::Path};
 use serde::Deserialize;
 use crate::app::App;
-use crate::stream::{ServerId, NetEvent};
-use std::collections::BTreeMap;
+use crate::stream::{ServerId, NetEvent, ConnOptions, Identity, Reconnect, FloodControl};
+use crate::cap::{Sasl, DEFAULT_CAPS, TWITCH_CAPS};
+use std::time::Duration;
+use crate::message::IrcMessage;
 use std::collections::btree_map::Entry;
 use crate::app::ServerData;
 use crate::app::ChannelData;
+use crate::app::ChannelMap;
+use crate::app::ChatLine;
+use crate::logger::{Logger, LogSettings};
+use crate::command;
+use crate::ctcp::CtcpSettings;
 
 // Root struct, all sections optional
 #[derive(Debug, Deserialize)]
@@ -14,11 +21,48 @@ struct Config {
     theme: Option<Theme>,
     twitch: Option<Twitch>,
     autojoin: Option<AutoJoin>,
+    reconnect: Option<ReconnectConfig>,
+    flood: Option<FloodConfig>,
+    logging: Option<LoggingConfig>,
+    aliases: Option<HashMap<String, AliasConfig>>,
+    ctcp: Option<CtcpConfig>,
+}
+
+// One command or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AliasConfig {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct LoggingConfig {
+    enabled: Option<bool>,
+    strip_formatting: Option<bool>,
+    exclude: Option<Vec<String>>,
+    path: Option<String>,
+    backlog: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CtcpConfig {
+    replies: Option<Vec<String>>,
+    version: Option<String>,
+    max_replies: Option<usize>,
+    per_seconds: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ClientConfig {
     nick: String,
+    timestamp_format: Option<String>,
+    rejoin_on_kick: Option<bool>,
+    sidebar: Option<bool>,
+    sidebar_width: Option<u16>,
+    nicklist: Option<bool>,
+    nicklist_width: Option<u16>,
+    nicklist_min_width: Option<u16>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,10 +74,47 @@ struct Theme {
     text: Vec<u8>,
 }
 
+#[derive(Debug, Deserialize)]
+struct ReconnectConfig {
+    enabled: Option<bool>,
+    max_attempts: Option<u32>,
+    delay: Option<u64>,
+    max_delay: Option<u64>,
+}
+
+impl ReconnectConfig {
+    fn to_reconnect(&self) -> Reconnect {
+        let default = Reconnect::default();
+        Reconnect {
+            enabled: self.enabled.unwrap_or(default.enabled),
+            max_attempts: self.max_attempts.unwrap_or(default.max_attempts),
+            base_delay: self.delay.map(Duration::from_secs).unwrap_or(default.base_delay),
+            max_delay: self.max_delay.map(Duration::from_secs).unwrap_or(default.max_delay),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FloodConfig {
+    burst: Option<u32>,
+    rate_ms: Option<u64>,
+    twitch: Option<Box<FloodConfig>>,
+}
+
+impl FloodConfig {
+    fn to_flood(&self, default: FloodControl) -> FloodControl {
+        FloodControl {
+            burst: self.burst.unwrap_or(default.burst),
+            refill: self.rate_ms.map(Duration::from_millis).unwrap_or(default.refill),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Twitch {
     nick: String,
     oauth: String,
+    caps: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -47,6 +128,41 @@ struct Server {
     ip: String,
     nick: String,
     channels: Vec<String>,
+    port: Option<u16>,
+    tls: Option<bool>,
+    tls_insecure: Option<bool>,
+    ca_file: Option<String>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    sasl_mechanism: Option<String>,
+    sasl_account: Option<String>,
+    sasl_password: Option<String>,
+    caps: Option<Vec<String>>,
+}
+
+impl Server {
+    fn conn_options(&self) -> ConnOptions {
+        let sasl = match self.sasl_mechanism.as_deref().map(|m| m.to_uppercase()) {
+            Some(m) if m == "PLAIN" => Some(Sasl::Plain {
+                account: self.sasl_account.clone().unwrap_or(self.nick.clone()),
+                password: self.sasl_password.clone().unwrap_or_default(),
+            }),
+            Some(m) if m == "EXTERNAL" => Some(Sasl::External),
+            _ => None,
+        };
+        ConnOptions {
+            identity: Identity::default(),
+            tls: self.tls.unwrap_or(false),
+            tls_insecure: self.tls_insecure.unwrap_or(false),
+            ca_file: self.ca_file.clone(),
+            client_cert: self.client_cert.clone(),
+            client_key: self.client_key.clone(),
+            sasl,
+            caps: self.caps.clone().unwrap_or(DEFAULT_CAPS.iter().map(|c| c.to_string()).collect()),
+            reconnect: Reconnect::default(),
+            flood: FloodControl::default(),
+        }
+    }
 }
 
 fn read_file() -> Result<Config, Box<dyn std::error::Error>> {
@@ -70,6 +186,24 @@ pub fn read_config(app: &mut App) {
         Ok(config) => {
             if let Some(conf) = &config.config {
                 app.active_nick = conf.nick.clone();
+                if let Some(format) = &conf.timestamp_format {
+                    // An invalid specifier would make formatting panic, keep the default then
+                    if !chrono::format::StrftimeItems::new(format).any(|i| i == chrono::format::Item::Error) {
+                        app.timestamp_format = format.clone();
+                    }
+                }
+                app.rejoin_on_kick = conf.rejoin_on_kick.unwrap_or(false);
+                app.sidebar = conf.sidebar.unwrap_or(true);
+                if let Some(width) = conf.sidebar_width {
+                    app.sidebar_width = width.clamp(8, 60);
+                }
+                app.nicklist = conf.nicklist.unwrap_or(true);
+                if let Some(width) = conf.nicklist_width {
+                    app.nicklist_width = width.clamp(8, 60);
+                }
+                if let Some(width) = conf.nicklist_min_width {
+                    app.nicklist_min_width = width;
+                }
                 if let Some(server) = app.server_list.get_mut(&"System".to_string()) {
                     server.nick = conf.nick.clone();
                 }
@@ -82,6 +216,56 @@ pub fn read_config(app: &mut App) {
     
 }
 
+pub fn read_logging(app: &mut App) {
+    let mut settings = LogSettings::default();
+    if let Ok(config) = read_file() {
+        if let Some(logging) = &config.logging {
+            settings.enabled = logging.enabled.unwrap_or(settings.enabled);
+            settings.strip_formatting = logging.strip_formatting.unwrap_or(settings.strip_formatting);
+            settings.exclude = logging.exclude.clone().unwrap_or_default();
+            settings.backlog = logging.backlog.unwrap_or(settings.backlog);
+            if let Some(path) = &logging.path {
+                settings.dir = path.into();
+            }
+        }
+    }
+    app.logger = Logger::start(settings);
+}
+
+pub fn read_aliases(app: &mut App) {
+    if let Ok(config) = read_file() {
+        if let Some(aliases) = &config.aliases {
+            for (name, value) in aliases {
+                let name = name.trim_start_matches('/').to_lowercase();
+                // Built in commands can not be replaced
+                if command::find(&name).is_some() {
+                    continue;
+                }
+                let commands = match value {
+                    AliasConfig::One(c) => command::split_alias(c),
+                    AliasConfig::Many(list) => list.iter().flat_map(|c| command::split_alias(c)).collect(),
+                };
+                app.aliases.insert(name, commands);
+            }
+        }
+    }
+}
+
+pub fn read_ctcp(app: &mut App) {
+    let mut settings = CtcpSettings::default();
+    if let Ok(config) = read_file() {
+        if let Some(ctcp) = &config.ctcp {
+            if let Some(replies) = &ctcp.replies {
+                settings.replies = replies.iter().map(|v| v.to_ascii_uppercase()).collect();
+            }
+            settings.version = ctcp.version.clone().unwrap_or(settings.version);
+            settings.max_replies = ctcp.max_replies.unwrap_or(settings.max_replies);
+            settings.window = ctcp.per_seconds.map(Duration::from_secs).unwrap_or(settings.window);
+        }
+    }
+    app.ctcp.settings = settings;
+}
+
 pub fn read_twitch() -> (String, String) {
     let config_result = read_file();
     
@@ -107,30 +291,38 @@ pub async fn read_autojoin (app: &mut App, net_tx: &tokio::sync::mpsc::Unbounded
             if let Some(auto) = &autojoin.autojoin {
                 for (_, server) in &auto.servers {
                     //app.chat_bounds(server.ip.to_string(), "System".to_string(), "Status".to_string(), "Error".to_string());
-                    let port = ":6667".to_string();
+                    let mut opts = server.conn_options();
+                    if let Some(reconnect) = &autojoin.reconnect {
+                        opts.reconnect = reconnect.to_reconnect();
+                    }
+                    if let Some(flood) = &autojoin.flood {
+                        opts.flood = flood.to_flood(FloodControl::default());
+                    }
+                    let port = server.port.unwrap_or(if opts.tls { 6697 } else { 6667 });
                     let server_id: String = server.ip.to_string();
-                    let addr: String = server_id.clone() + &port;
-
-                    if app.stream_mgr.connect(server_id.clone(), addr.to_string(), net_tx.clone(), server.nick.clone(), app.real.clone(), "".to_string()).await {
-                        match app.server_list.entry(server_id.clone()) {
-                            Entry::Occupied(o) => o.into_mut(),
-                            Entry::Vacant(v) => {
-                            // Create a new HashMap with the "Status" channel already inserted
-                                let mut channels = BTreeMap::new();
-                                channels.insert("Status".to_string(), ChannelData::default());
-                                v.insert(ServerData {
-                                    channels,
-                                    nick: server.nick.clone(),
-                                })
-                            }
-                        };
-                        app.active_server = server_id.clone();
-                        app.active_channel = "Status".to_string();
-                        app.active_nick = server.nick.clone();
-                        if let Some(server) =  app.server_list.get_mut(&server_id) {
-                            if let Some(channel) = server.channels.get_mut(&"Status".to_string()) {
-                                channel.chat_list.push(("System".to_string(),String::from(format!("<connecting to {}>", addr))));
-                            }
+                    let addr: String = format!("{}:{}", server_id, port);
+
+                    opts.identity = Identity::new(&server.nick, &app.real);
+                    app.stream_mgr.connect(server_id.clone(), addr.to_string(), net_tx.clone(), opts);
+                    match app.server_list.entry(server_id.clone()) {
+                        Entry::Occupied(o) => o.into_mut(),
+                        Entry::Vacant(v) => {
+                        // Create a new HashMap with the "Status" channel already inserted
+                            let mut channels = ChannelMap::default();
+                            channels.insert("Status".to_string(), ChannelData::default());
+                            v.insert(ServerData {
+                                channels,
+                                nick: server.nick.clone(),
+                                ..Default::default()
+                            })
+                        }
+                    };
+                    app.active_server = server_id.clone();
+                    app.active_channel = "Status".to_string();
+                    app.active_nick = server.nick.clone();
+                    if let Some(server) =  app.server_list.get_mut(&server_id) {
+                        if let Some(channel) = server.channels.get_mut(&"Status".to_string()) {
+                            channel.chat_list.push(ChatLine::new("System", &format!("<connecting to {}>", addr)));
                         }
                     }
                 }
@@ -142,6 +334,41 @@ pub async fn read_autojoin (app: &mut App, net_tx: &tokio::sync::mpsc::Unbounded
     }
 } 
 
+// Per server connection settings, taken from the autojoin entry with a matching ip
+pub fn server_options(server_id: &str) -> ConnOptions {
+    let mut opts = ConnOptions::default();
+    if let Ok(config) = read_file() {
+        if let Some(auto) = &config.autojoin {
+            if let Some(server) = auto.servers.values().find(|s| s.ip == server_id) {
+                opts = server.conn_options();
+            }
+        }
+        if let Some(reconnect) = &config.reconnect {
+            opts.reconnect = reconnect.to_reconnect();
+        }
+        if let Some(flood) = &config.flood {
+            opts.flood = flood.to_flood(FloodControl::default());
+        }
+    }
+    opts
+}
+
+// Same as server_options, but with the stricter twitch send limits
+pub fn twitch_options(server_id: &str) -> ConnOptions {
+    let mut opts = server_options(server_id);
+    opts.flood = FloodControl::twitch();
+    opts.caps = TWITCH_CAPS.iter().map(|c| c.to_string()).collect();
+    if let Ok(config) = read_file() {
+        if let Some(caps) = config.twitch.as_ref().and_then(|t| t.caps.clone()) {
+            opts.caps = caps;
+        }
+        if let Some(twitch) = config.flood.as_ref().and_then(|f| f.twitch.as_ref()) {
+            opts.flood = twitch.to_flood(FloodControl::twitch());
+        }
+    }
+    opts
+}
+
 pub fn autojoin_channel (app: &mut App, server_id: ServerId) {
     let config_result = read_file();
     
@@ -151,7 +378,11 @@ pub fn autojoin_channel (app: &mut App, server_id: ServerId) {
                 for (_, server) in &auto.servers {
                     if server.ip == server_id {
                         for channel in server.channels.clone() {
-                            app.stream_mgr.send_line(server_id.clone(), "JOIN ".to_string() + &channel);
+                            // Already open channels are rejoined after a reconnect
+                            if app.server_list.get(&server_id).is_some_and(|s| s.channels.contains_key(&channel)) {
+                                continue;
+                            }
+                            app.stream_mgr.send_line(server_id.clone(), IrcMessage::new("JOIN", &[&channel]).to_string());
                         }
                     }
                 }
@@ -0,0 +1,145 @@
+// ctcp.rs
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use chrono::Local;
+
+// Verbs answered automatically, ACTION is always understood
+pub const REPLY_VERBS: [&str; 4] = ["VERSION", "PING", "TIME", "CLIENTINFO"];
+
+#[derive(Debug, Clone)]
+pub struct CtcpSettings {
+    pub replies: Vec<String>,
+    pub version: String,
+    // At most max_replies answers per window on each server
+    pub max_replies: usize,
+    pub window: Duration,
+}
+
+impl Default for CtcpSettings {
+    fn default() -> Self {
+        Self {
+            replies: REPLY_VERBS.iter().map(|v| v.to_string()).collect(),
+            version: format!("RustyChat {}", env!("CARGO_PKG_VERSION")),
+            max_replies: 3,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Ctcp {
+    pub settings: CtcpSettings,
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+// "\x01VERB args\x01" -> (VERB, args), the closing \x01 is optional
+pub fn parse(text: &str) -> Option<(String, String)> {
+    let inner = text.strip_prefix('\u{1}')?;
+    let inner = inner.strip_suffix('\u{1}').unwrap_or(inner);
+    let (verb, args) = inner.split_once(' ').unwrap_or((inner, ""));
+    if verb.is_empty() {
+        return None;
+    }
+    Some((verb.to_ascii_uppercase(), args.to_string()))
+}
+
+pub fn encode(verb: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("\u{1}{}\u{1}", verb.to_ascii_uppercase())
+    } else {
+        format!("\u{1}{} {}\u{1}", verb.to_ascii_uppercase(), args)
+    }
+}
+
+impl Ctcp {
+    // Text of the NOTICE answering a request, None when the verb is not answered or the limit is hit
+    pub fn reply(&mut self, server_id: &str, verb: &str, args: &str) -> Option<String> {
+        if !self.settings.replies.iter().any(|v| v.eq_ignore_ascii_case(verb)) {
+            return None;
+        }
+        let text = match verb {
+            "VERSION" => self.settings.version.clone(),
+            "PING" => args.to_string(),
+            "TIME" => Local::now().to_rfc2822(),
+            "CLIENTINFO" => {
+                let mut verbs: Vec<String> = self.settings.replies.iter().map(|v| v.to_ascii_uppercase()).collect();
+                verbs.push("ACTION".to_string());
+                verbs.sort();
+                verbs.join(" ")
+            }
+            _ => return None,
+        };
+
+        let now = Instant::now();
+        let sent = self.sent.entry(server_id.to_string()).or_default();
+        while sent.front().is_some_and(|t| now.duration_since(*t) > self.settings.window) {
+            sent.pop_front();
+        }
+        if sent.len() >= self.settings.max_replies {
+            return None;
+        }
+        sent.push_back(now);
+        Some(encode(verb, &text))
+    }
+}
+
+// Milliseconds since the epoch, sent with /ctcp nick PING to measure the round trip
+pub fn ping_stamp() -> String {
+    Local::now().timestamp_millis().to_string()
+}
+
+// Round trip of a PING reply carrying a ping_stamp
+pub fn ping_lag(args: &str) -> Option<f64> {
+    let sent = args.trim().parse::<i64>().ok()?;
+    let lag = Local::now().timestamp_millis() - sent;
+    if lag < 0 {
+        return None;
+    }
+    Some(lag as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(parse("\u{1}VERSION\u{1}"), Some(("VERSION".to_string(), String::new())));
+        assert_eq!(parse("\u{1}ping 12345\u{1}"), Some(("PING".to_string(), "12345".to_string())));
+        assert_eq!(parse("\u{1}ACTION waves at you\u{1}"), Some(("ACTION".to_string(), "waves at you".to_string())));
+        // Some clients leave out the closing \x01
+        assert_eq!(parse("\u{1}ACTION waves"), Some(("ACTION".to_string(), "waves".to_string())));
+        assert_eq!(parse("\u{1}\u{1}"), None);
+        assert_eq!(parse("\u{1}"), None);
+        assert_eq!(parse("plain text"), None);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        assert_eq!(encode("version", ""), "\u{1}VERSION\u{1}");
+        assert_eq!(parse(&encode("ping", "1 2")), Some(("PING".to_string(), "1 2".to_string())));
+    }
+
+    #[test]
+    fn only_configured_verbs_are_answered() {
+        let mut ctcp = Ctcp::default();
+        ctcp.settings.replies = vec!["ping".to_string(), "CLIENTINFO".to_string()];
+        assert_eq!(ctcp.reply("srv", "PING", "42"), Some("\u{1}PING 42\u{1}".to_string()));
+        assert_eq!(ctcp.reply("srv", "CLIENTINFO", ""), Some("\u{1}CLIENTINFO ACTION CLIENTINFO PING\u{1}".to_string()));
+        assert_eq!(ctcp.reply("srv", "VERSION", ""), None);
+    }
+
+    #[test]
+    fn replies_are_rate_limited_per_server() {
+        let mut ctcp = Ctcp::default();
+        ctcp.settings.max_replies = 2;
+        ctcp.settings.window = Duration::from_millis(100);
+        assert!(ctcp.reply("a", "PING", "1").is_some());
+        assert!(ctcp.reply("a", "PING", "2").is_some());
+        assert!(ctcp.reply("a", "PING", "3").is_none());
+        // Another server has its own budget
+        assert!(ctcp.reply("b", "PING", "1").is_some());
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(ctcp.reply("a", "PING", "4").is_some());
+    }
+}
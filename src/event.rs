@@ -1,20 +1,22 @@
 // event.rs
 use crate::app::App;
 use crate::app::Popup;
-use crate::config;
-use crate::app::ServerData;
 //use std::fs;
-use crate::app::ChannelData;
 //use crate::tui;
 use ratatui::text::Span;
 use crate::stream::{ServerId, NetEvent};
-use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers, MouseEventKind, MouseButton};
 use tokio::sync::mpsc::UnboundedSender;
-use regex::Regex;
-use std::collections::BTreeMap;
-use std::collections::btree_map::Entry;
 use textwrap::{wrap, Options};
 use crate::cursor;
+use crate::textstyle;
+use crate::layout::Dir;
+use crate::search;
+use crate::complete;
+use crate::command;
+use crate::userlist;
+use crate::chanlist;
+use crate::message::IrcMessage;
 
 pub enum AppEvent {
     InputEdit(char),
@@ -29,7 +31,15 @@ pub enum AppEvent {
     InputEscape,
     ListHistory(KeyCode),
     SplitSwap,
+    // Ctrl + arrow, resizes the focused pane
+    ResizePane(KeyCode),
     StyleSwitch(char),
+    Search,
+    Complete,
+    // Alt + letter, actions in the user popup or joining an invite, Alt + number switches buffer
+    AltKey(char),
+    // Left click at column, row
+    Click(u16, u16),
 }
 
 static CTRL_KEYS: &[char] = &['s', 'b', 'k', 'u', 'n'];
@@ -41,10 +51,23 @@ pub async fn input_event_loop(sender: UnboundedSender<AppEvent>) {
                 Event::Resize(nw, nh ) => {
                     if sender.send(AppEvent::Resize(nw, nh)).is_err() { break; }
                 }
+                Event::Mouse(mouse) => {
+                    if let MouseEventKind::Down(MouseButton::Left) = mouse.kind {
+                        if sender.send(AppEvent::Click(mouse.column, mouse.row)).is_err() { break; }
+                    }
+                }
                 Event::Key(key) => {
+                    if key.modifiers.contains(KeyModifiers::CONTROL) && matches!(key.code, KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down) {
+                        if sender.send(AppEvent::ResizePane(key.code)).is_err() { break; }
+                        continue;
+                    }
                     match key.code {
                         KeyCode::Char(c) => {
-                            if key.modifiers.contains(KeyModifiers::CONTROL) && CTRL_KEYS.contains(&c) {
+                            if key.modifiers.contains(KeyModifiers::CONTROL) && c == 'f' {
+                                if sender.send(AppEvent::Search).is_err() { break; }
+                            } else if key.modifiers.contains(KeyModifiers::ALT) {
+                                if sender.send(AppEvent::AltKey(c)).is_err() { break; }
+                            } else if key.modifiers.contains(KeyModifiers::CONTROL) && CTRL_KEYS.contains(&c) {
                                 if sender.send(AppEvent::StyleSwitch(c)).is_err() { break; }
                             } else {
                                 if sender.send(AppEvent::InputEdit(c)).is_err() { break; }
@@ -84,6 +107,9 @@ pub async fn input_event_loop(sender: UnboundedSender<AppEvent>) {
                             if sender.send(AppEvent::ListHistory(key.code)).is_err() { break; }
                         }
                         KeyCode::Tab => {
+                            if sender.send(AppEvent::Complete).is_err() { break; }
+                        }
+                        KeyCode::BackTab => {
                             if sender.send(AppEvent::SplitSwap).is_err() { break; }
                         }
                         _ => {}
@@ -101,11 +127,60 @@ pub async fn input_event_loop(sender: UnboundedSender<AppEvent>) {
 
 // For main loop to use:
 pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mpsc::UnboundedSender<(ServerId, NetEvent)>) {
+    //Any key other than Tab accepts the current completion
+    if !matches!(ev, AppEvent::Complete) {
+        app.completion = None;
+    }
     match ev {
+        AppEvent::Complete => {
+            // With nothing typed Tab moves to the next pane
+            let count = app.layout.panes().len();
+            if app.prompt.is_empty() && count > 1 {
+                let focus = (app.layout.focus + 1) % count;
+                app.focus_pane(focus);
+                return;
+            }
+            complete::complete(app);
+        }
         AppEvent::InputEdit(c) => { 
             //app.prompt.push(c); 
             //tui::enter_char(c, app);
+            if app.popup == Popup::User {
+                userlist::type_char(app, c);
+                return;
+            }
+            if app.popup == Popup::List {
+                chanlist::type_char(app, c);
+                return;
+            }
             cursor::enter_char(app, c);
+            search::update_incremental(app);
+        }
+        AppEvent::AltKey(c) => {
+            if let Some(number) = c.to_digit(10) {
+                switch_to_number(app, number as usize);
+            } else if app.popup == Popup::User {
+                userlist::action(app, c);
+            } else if app.popup == Popup::List {
+                if c == 's' {
+                    chanlist::toggle_sort(app);
+                }
+            } else if c == 'i' {
+                if let Some((server_id, channel)) = app.invite.take() {
+                    if app.server_list.contains_key(&server_id) {
+                        app.stream_mgr.send_line(server_id, IrcMessage::new("JOIN", &[&channel]).to_string());
+                    }
+                }
+            } else {
+                cursor::enter_char(app, c);
+            }
+        }
+        AppEvent::Search => {
+            if app.search.incremental {
+                search::jump(app, 1);
+            } else {
+                search::start_incremental(app);
+            }
         }
         AppEvent::KeyLeft => {
             //tui::move_cursor_left(app);
@@ -216,26 +291,41 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
             }
         }
         AppEvent::SplitSwap => {
-            let (toggle, server_left, left, server_right, right) = &app.split;
-            if *toggle == true {
-                if app.active_server == *server_left && app.active_channel == *left {
-                    //app.chat_pos = 0;
-                    app.active_server = server_right.to_string() ;
-                    app.active_channel = right.to_string();
-                    if let Some(server) = app.server_list.get(&server_right.clone()) {
-                        app.active_nick = server.nick.clone();
-                    }
-                } else {
-                   // app.chat_pos = 0;
-                    app.active_server = server_left.to_string();
-                    app.active_channel = left.to_string();
-                    if let Some(server) = app.server_list.get(&server_left.clone()) {
-                        app.active_nick = server.nick.clone();
-                    }
-                }
+            // Shift+Tab, the previous pane
+            let count = app.layout.panes().len();
+            if count > 1 {
+                let focus = (app.layout.focus + count - 1) % count;
+                app.focus_pane(focus);
+            }
+        }
+        AppEvent::ResizePane(key) => {
+            // Ctrl+arrows move the divider next to the focused pane
+            match key {
+                KeyCode::Left => app.layout.resize(Dir::Vertical, -5),
+                KeyCode::Right => app.layout.resize(Dir::Vertical, 5),
+                KeyCode::Up => app.layout.resize(Dir::Horizontal, -5),
+                KeyCode::Down => app.layout.resize(Dir::Horizontal, 5),
+                _ => {}
             }
         }
         AppEvent::PromptHistory(key) => {
+            if app.popup == Popup::Search {
+                let last = app.search.results.len().saturating_sub(1);
+                match (key, app.search.state.selected()) {
+                    (KeyCode::Up, Some(i)) => app.search.state.select(Some(i.saturating_sub(1))),
+                    (KeyCode::Down, Some(i)) => app.search.state.select(Some((i + 1).min(last))),
+                    _ => {}
+                }
+                return;
+            }
+            if app.popup == Popup::User {
+                userlist::move_selection(app, if key == KeyCode::Up { -1 } else { 1 });
+                return;
+            }
+            if app.popup == Popup::List {
+                chanlist::move_selection(app, if key == KeyCode::Up { -1 } else { 1 });
+                return;
+            }
             match key {
                 KeyCode::Up => {
                     //Handle Up
@@ -278,30 +368,25 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
                 KeyCode::PageUp => {
                     //Handle PageUp
                     if app.popup == Popup::List {
-                        app.list_pos = app.list_pos.saturating_sub(1);
+                        chanlist::move_selection(app, -10);
                     } else if app.popup == Popup::User {
-                        app.menu_pos = app.menu_pos.saturating_sub(1);
+                        userlist::move_selection(app, -10);
                     } else {
+                        let prefix_width = app.prefix_width();
+                        let rows = app.chat_rows(&app.active_server, &app.active_channel);
+                        let wrap_width = textstyle::wrap_width(app, &app.active_server, &app.active_channel, prefix_width);
                         if let Some(server) = app.server_list.get_mut(&app.active_server) {
                             if let Some(channel) = server.channels.get_mut(&app.active_channel) {
                                 
-                                let raw_lines: Vec<&str> = channel.chat_list.iter().map(|(_, line)| line.as_str()).collect(); 
-                                let wrap_width;
+                                let raw_lines: Vec<&str> = channel.chat_list.iter().map(|line| line.text.as_str()).collect();
                                 //chat window horizontal "linewrap"
-                                let (on, _, _, _, _) = app.split;
-                                if on == true {
-                                    wrap_width = (app.w as usize / 2) - 6 - 12;
-                                } else {
-                                    wrap_width = app.w as usize-4 - 12;
-                                }
-
                                 let wrap_options = Options::new(wrap_width).break_words(false);
                                 let wrapped_lines: Vec<String> = raw_lines.iter()
                                   .flat_map(|line| wrap(line, &wrap_options))
                                     .map(|cow| cow.into_owned())
                                     .collect();
                                 
-                                if channel.chat_pos == wrapped_lines.len().saturating_sub(app.h as usize - 6) {
+                                if channel.chat_pos == wrapped_lines.len().saturating_sub(rows) {
                                     //Strop Scroll
                                 } else {
                                     channel.chat_pos = channel.chat_pos.saturating_add(1);
@@ -313,24 +398,9 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
                 KeyCode::PageDown => {
                     //Handle PageDown
                     if app.popup == Popup::List {
-                        if app.list_pos >= app.list_response.len().saturating_sub(((app.h as usize * 70) / 100) - 1) {
-                            //Stop Scrolling
-                        } else {
-                            app.list_pos = app.list_pos.saturating_add(1);
-                        }
+                        chanlist::move_selection(app, 10);
                     } else if app.popup == Popup::User {
-                        //let mut user_length: usize = 0;
-                        if let Some(server) = app.server_list.get(&app.active_server) {
-                            if let Some(channel) = server.channels.get(&app.active_channel) {
-                                let user_length = channel.user_list.len();
-
-                                if app.menu_pos >= user_length.saturating_sub(((app.h as usize * 70) / 100) - 1) {
-                                    //Stop Scrolloing
-                                } else {
-                                  app.menu_pos = app.menu_pos.saturating_add(1);
-                                }
-                            };
-                        };
+                        userlist::move_selection(app, 10);
                     } else {
                         if let Some(server) = app.server_list.get_mut(&app.active_server) {
                             if let Some(channel) = server.channels.get_mut(&app.active_channel) {
@@ -344,22 +414,44 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
         }
         AppEvent::InputBackspace => { 
             //tui::delete_char(app);
+            if app.popup == Popup::User {
+                userlist::backspace(app);
+                return;
+            }
+            if app.popup == Popup::List {
+                chanlist::backspace(app);
+                return;
+            }
             cursor::delete_char(app);
+            search::update_incremental(app);
         }
         AppEvent::InputDelete => {
             if app.character_index == app.prompt.len() {
                 } else if app.prompt.len() > 0 {
                     app.prompt.remove(app.character_index);
                 }
+            search::update_incremental(app);
         }
         AppEvent::InputEscape => {
+            if app.popup == Popup::List {
+                chanlist::close(app);
+            }
             app.popup = Popup::None;
-            app.list_response.clear();
+            search::stop(app);
             /*if app.list_popup == true {
                 app.list_popup = false;
                 app.list_response.clear();
             }*/
         }
+        AppEvent::Click(column, row) => {
+            // Only the sidebar takes clicks
+            let area = app.sidebar_area;
+            if app.sidebar && area.contains(ratatui::layout::Position::new(column, row)) {
+                if let Some(Some(number)) = app.sidebar_rows.get((row - area.y) as usize).copied() {
+                    switch_to_number(app, number);
+                }
+            }
+        }
         AppEvent::Resize(nw, nh) => { 
             app.w = nw;
             app.h = nh;
@@ -380,11 +472,15 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
                         app.popup = Popup::None;
                         app.active_tab = usize::MAX;
                     } else {
-                        app.popup = Popup::User;
-                        app.active_tab = 1;
-                        app.menu_pos = 0;
+                        userlist::open(app);
                     }
                 },
+                KeyCode::F(4) => {
+                    app.sidebar = !app.sidebar;
+                },
+                KeyCode::F(5) => {
+                    app.nicklist = !app.nicklist;
+                },
                 KeyCode::F(3) => {
                     if app.popup == Popup::Channel {
                         app.popup = Popup::None;
@@ -399,325 +495,41 @@ pub async fn handle_input(app: &mut App, ev: AppEvent, net_tx: &tokio::sync::mps
 
         }
         AppEvent::InputSend => {
+            if app.popup == Popup::User {
+                userlist::enter(app);
+                return;
+            }
+            if app.popup == Popup::List {
+                chanlist::enter(app);
+                return;
+            }
+            if app.popup == Popup::Search {
+                search::open_selected(app);
+                app.popup = Popup::None;
+                return;
+            }
+            if app.search.incremental {
+                //Enter steps to the next older match, Esc leaves search
+                search::jump(app, 1);
+                return;
+            }
             let line = app.prompt.clone();
             app.prompt_list.push(line.clone());
             app.prompt_pos = app.prompt_list.len();
-            if !line.is_empty() {
-                match &line {
-                    s if s.to_uppercase().starts_with("/TWITCH_CONNECT") => {
-                        //Handle Tiwtch Connection
-                        let tw_serv = "irc.chat.twitch.tv";
-                        let tw_port = ":6667";
-                        //match fs::read_to_string("oauth") {
-                        //    Ok(n) => {
-                        let (tw_nick, oauth) = config::read_twitch();
-                        if oauth == "Error" {
-                            app.chat_bounds(tw_nick.to_owned(), app.active_server.clone(), app.active_channel.clone(), "ERROR".to_string());
-                        } else {
-                                //let mut oauth_iter = n.lines();
-                                //let tw_nick = oauth_iter.next().unwrap_or(&app.active_nick.to_string()).to_string();
-                                //let oauth = oauth_iter.next().unwrap_or("");
-                                
-                            if app.stream_mgr.connect(tw_serv.to_string().clone(), tw_serv.to_owned() + tw_port, net_tx.clone(), tw_nick.clone(), app.real.clone(), oauth.to_string()).await {
-                                match app.server_list.entry(tw_serv.to_string().clone()) {
-                                    Entry::Occupied(o) => o.into_mut(),
-                                    Entry::Vacant(v) => {
-                                    // Create a new HashMap with the "Status" channel already inserted
-                                        let mut channels = BTreeMap::new();
-                                        channels.insert("Status".to_string(), ChannelData::default());
-                                        v.insert(ServerData {
-                                            channels,
-                                            nick: tw_nick.clone(),
-                                        })
-                                    }
-                                };
-                                app.active_server = tw_serv.to_string().clone();
-                                app.active_channel = "Status".to_string();
-                                app.active_nick = tw_nick.to_string();
-                                if let Some(server) =  app.server_list.get_mut(&mut app.active_server) {
-                                    if let Some(channel) = server.channels.get_mut(&app.active_channel) {
-                                        channel.chat_list.push(("System".to_string(), String::from(format!("<connecting to {}>", tw_serv.to_owned() + tw_port))));
-                                    }
-                                }
-                            }
-                        }
-                                //}
-                                //Err(_) => {
-                                //    app.chat_bounds("Error found!".to_owned(), app.active_server.clone(), app.active_channel.clone(), "ERROR".to_string());
-                                //}
-                            //}
-                    }
-                    s if s.to_uppercase().starts_with("/CONNECT") => {
-                        let port = ":6667".to_string();
-                        let mut server_id: String = "".to_string();
-                        let mut addr:String = "".to_string();
-                        let re = Regex::new(r"^/connect\s+([^\s:]+)(?::(\d+))?$").unwrap();
-                        if re.is_match(&line) {
-                            let conn = re.captures(&line).unwrap();
-                            let serv_group = conn.get(1).map_or("", |m| m.as_str());
-                            let port_group = conn.get(2).map_or("", |m| m.as_str());
-                            server_id = serv_group.to_string();
-                            if port_group.is_empty() {
-                                addr = serv_group.to_string() + &port;
-                            } else {
-                                addr = serv_group.to_string() + ":" + &port_group;
-                            }
-                        }
- 
-                        if app.stream_mgr.connect(server_id.clone(), addr.to_string(), net_tx.clone(), app.active_nick.clone(), app.real.clone(), "".to_string()).await {
-                            match app.server_list.entry(server_id.clone()) {
-                                Entry::Occupied(o) => o.into_mut(),
-                                Entry::Vacant(v) => {
-                                // Create a new HashMap with the "Status" channel already inserted
-                                    let mut channels = BTreeMap::new();
-                                    channels.insert("Status".to_string(), ChannelData::default());
-                                    v.insert(ServerData {
-                                        channels,
-                                        nick: app.active_nick.clone(),
-                                    })
-                                }
-                            };
-                            app.active_server = server_id.clone();
-                            app.active_channel = "Status".to_string();
-                            if let Some(server) =  app.server_list.get_mut(&mut app.active_server) {
-                                if let Some(channel) = server.channels.get_mut(&app.active_channel) {
-                                    channel.chat_list.push(("System".to_string(),String::from(format!("<connecting to {}>", addr))));
-                                }
-                            }
-                        } 
-                    }
-                    s if s.to_uppercase().starts_with("/QUIT") => {
-                        app.quit();
-                    }
-                    s if s.to_uppercase() == "/LOAD" => {
-                        config::read_autojoin(app, &net_tx).await;
-                    }
-                    s if s.to_uppercase().starts_with("/DISCONNECT ") => {
-                        let server_id = &line[12..];
-                        if app.server_list.contains_key(server_id) {
-                            app.stream_mgr.disconnect(server_id);
-                            app.server_list.remove(server_id);
-                            //app.chat_bounds(server_id.to_string(), "System".to_string(), "Status".to_string(), "test".to_string());
-                            let (on, left_server, left_chan, right_server, right_chan) = app.split.clone();
-                            if on {
-                                if server_id == left_server {
-                                    if app.active_server == left_server {
-                                        app.split = (true, "System".to_string(), "Status".to_string(), right_server.clone(), right_chan.clone());
-                                        app.active_server = "System".to_string();
-                                        app.active_channel = "Status".to_string();
-                                    } else {
-                                        app.split = (true, "System".to_string(), "Status".to_string(), right_server.clone(), right_chan.clone());
-                                    }
-                                } else if server_id == right_server {
-                                    if app.active_server == right_server {
-                                        app.split = (true, left_server, left_chan, "System".to_string(), "Status".to_string());
-                                        app.active_server = "System".to_string();
-                                        app.active_channel = "Status".to_string();
-                                    } else  {
-                                        app.split = (true, left_server, left_chan, "System".to_string(), "Status".to_string());
-                                    }
-                                }
-                            } else {
-                                if app.active_server == server_id {
-                                    app.active_server = "System".to_string();
-                                    app.active_channel = "Status".to_string();
-                                }
-                            }
-                        }                        
-                    }
-                    s if s.to_uppercase().starts_with("/JOIN #") => {
-                        let prompt_command = line[1..line.len()].to_owned();
-                        if app.active_server!= "System" {
-                            app.stream_mgr.send_line(app.active_server.clone(), prompt_command);
-                        } else {
-                            app.chat_bounds("Error Not Connected to a server".to_owned(), "System".to_string(), "Status".to_string(), "ERROR".to_string());
-                        }
-                    }
-                    s if s.to_uppercase().starts_with("/PART") => {
-                        let prompt_write = line[1..].to_string();
-                        if line.contains('#') {
-                            app.stream_mgr.send_line(app.active_server.clone(), prompt_write);
-                        }
-                        if let Some(server) = app.server_list.get_mut(&app.active_server) {
-                            if server.channels.contains_key(&line[6..]) {
-                                server.channels.remove(&line[6..]);
-                                
-                                let (on, left_server, left_chan, right_server, right_chan) = app.split.clone();
-                                if on {
-                                    if app.active_channel == &line[6..] {
-                                        app.split = (true, "System".to_string(), "Status".to_string(), right_server, right_chan);
-                                        app.active_server = "System".to_string();
-                                        app.active_channel = "Status".to_string();
-                                    } else if app.active_channel == right_chan {
-                                        app.split = (true, left_server, left_chan, "System".to_string(), "Status".to_string());
-                                        app.active_server = "System".to_string();
-                                        app.active_channel = "Status".to_string();
-                                    }
-                                } else {
-                                    if app.active_channel == &line[6..] {
-                                        app.active_server = "System".to_string();
-                                        app.active_channel = "Status".to_string();
-                                }
-                            }
-                                //app.active_server = "System".to_string();
-                                //app.active_channel = "Status".to_string();
-                            } else {
-                                app.chat_bounds("Channel Not Joined".to_string(), app.active_server.clone(), app.active_channel.clone(), "Error".to_string())
-                            }
-                        }
-                    }
-                    s if s.to_uppercase().starts_with("/NICK") => {
-                        if app.active_server != "System" {
-                            let prompt_command = line[1..line.len()].to_owned();
-                            app.stream_mgr.send_line(app.active_server.clone(), prompt_command);
-                        } else {
-                            app.active_nick = line[6..].to_string();
-                            app.chat_bounds("You're now known as ".to_owned() + &app.active_nick, "System".to_string(), "Status".to_string(), "NICK".to_string())
-                        }
-                    }
-                    s if s.to_uppercase().starts_with("/MSG") => {
-                        if app.active_server != "System" {
-                            let mut input = line.split_whitespace();
-                            let _ = input.next();
-                            let nick = input.next();
-                            let msg_pos = line.find(nick.unwrap_or(""));
-                            let mut msg = line[msg_pos.unwrap_or(0) as usize + nick.unwrap_or("").len()..].to_string();
-
-                            while let Some(start) = msg.find("\\u{") {
-                                if let Some(end) = msg[start..].find('}') {
-                                    let end = start + end + 1;
-                                    let unicode_escape = &msg[start..end];
-                                    let hex_value = &unicode_escape[3..unicode_escape.len() - 1];
-
-                                    if let Ok(code_point) = u32::from_str_radix(hex_value, 16) {
-                                        if let Some(character) = char::from_u32(code_point) {
-                                            msg = format!(
-                                                "{}{}{}",
-                                                &msg[..start],
-                                                character,
-                                                &msg[end..]
-                                            );
-                                        }
-                                    }
-                                } else {
-                                    break;
-                                }
-                            }
-
-                            let prompt_write = "PRIVMSG ".to_owned() + &nick.unwrap_or("") + " :" + &msg;
-                            app.stream_mgr.send_line(app.active_server.clone(), prompt_write);
-                            app.chat_bounds(msg.clone(), app.active_server.clone(), app.active_channel.clone(), app.active_nick.clone());
-                            app.chat_bounds(msg.clone(), app.active_server.clone(), nick.unwrap_or("").to_string(), app.active_nick.clone());
-                        } else {
-                            app.chat_bounds("Error Not Connected, or wrong server".to_owned(), "System".to_string(), "Status".to_string(), "Error".to_string())
-                        }
-
-                    }
-                    s if s.to_uppercase().starts_with("/SWAP") => {
-                        let swap_nr: &str = &s[6..];
-                        if let Ok(nr) = swap_nr.parse::<usize>() {
-                            let mut channel_lines: (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
-
-                            for (outer_key, inner_map) in &app.server_list {
-                                //channel_lines.push(Line::from(outer_key.to_owned()));
-                                for (inner_key, _data) in &inner_map.channels {
-                                    channel_lines.1.push(format!("{}", inner_key));
-                                    channel_lines.0.push(format!("{}", outer_key));
-                                }
-
-                            }
-                            if nr < channel_lines.0.len() {
-                                app.split = (false, String::new(),String::new(),String::new(),String::new());
-                                app.active_server = channel_lines.0[nr].clone();
-                                app.active_channel = channel_lines.1[nr].clone();
-                                if let Some(server) = app.server_list.get_mut(&channel_lines.0[nr].clone()) {
-                                    app.active_nick = server.nick.clone();
-                                    if let Some(channel) = server.channels.get_mut(&channel_lines.1[nr].clone()) {
-                                        channel.chat_pos = 0;
-                                        channel.notification = false;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    s if s.to_uppercase().starts_with("/SPLIT") => {
-                        let center_byte = &s.find('-');
-                        let nr_left: &str = &s[7..center_byte.unwrap_or(7)];
-                        let nr_right: &str = &s[center_byte.unwrap_or(0)+1..];
- 
-                        if let (Ok(left), Ok(right)) = (nr_left.parse::<usize>(), nr_right.parse::<usize>()) {
-                            let mut channel_lines: (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
-
-                            for (outer_key, inner_map) in &app.server_list {
-                                for (inner_key, _data) in &inner_map.channels {
-                                    channel_lines.1.push(format!("{}",inner_key));
-                                    channel_lines.0.push(format!("{}",outer_key));
-                                }
-                            }
-                            if left < channel_lines.0.len() && right < channel_lines.0.len() {
-                                app.split = (true, channel_lines.0[left].clone(), channel_lines.1[left].clone(), channel_lines.0[right].clone(), channel_lines.1[right].clone());
-                                app.active_server = channel_lines.0[left].clone();
-                                app.active_channel = channel_lines.1[left].clone();
-                                if let Some(server) = app.server_list.get_mut(&channel_lines.0[left].clone()) {
-                                    app.active_nick = server.nick.clone();
-                                    if let Some(channel) = server.channels.get_mut(&channel_lines.1[left].clone()) {
-                                        channel.chat_pos = 0;
-                                        channel.notification = false;
-                                    }
-                                }
-                                if let Some(server) = app.server_list.get_mut(&channel_lines.0[right].clone()) {
-                                    if let Some(channel) = server.channels.get_mut(&channel_lines.1[right].clone()) {
-                                        channel.chat_pos = 0;
-                                        channel.notification = false;
-                                    }
-                                }
-
-                            }
-                        }
-                    }
-                    s if s.to_uppercase().starts_with("/") => {
-                        let prompt_command = line[1..line.len()].to_owned();
-                        app.stream_mgr.send_line(app.active_server.clone(), prompt_command);
-                    }
-                    _ => {
-                        // process line, send, etc
-                        if app.active_server != "System" {
-                            let mut result = String::from(line);
-                            while let Some(start) = result.find("\\u{") {
-                                if let Some(end) = result[start..].find('}') {
-                                    let end = start + end + 1;
-                                    let unicode_escape = &result[start..end];
-                                    let hex_value = &unicode_escape[3..unicode_escape.len() - 1];
-
-                                    if let Ok(code_point) = u32::from_str_radix(hex_value, 16) {
-                                        if let Some(character) = char::from_u32(code_point) {
-                                            result = format!(
-                                                "{}{}{}",
-                                                &result[..start],
-                                                character,
-                                                &result[end..]
-                                            );
-                                        }
-                                    }
-                                } else {
-                                    break;
-                                }
-                            }
-                            let prompt_write = "PRIVMSG ".to_owned() + &app.active_channel + " :" + &result;
-                            app.stream_mgr.send_line(app.active_server.clone(), prompt_write);
-                            app.chat_bounds(result.clone(), app.active_server.clone(), app.active_channel.clone(), app.active_nick.clone())
-                        } else {
-                            app.chat_bounds("Error currently not connected to a server or in a channel".to_owned(), "System".to_owned(), "Status".to_owned(), "Error".to_string());
-                        }
-                    }
-                }
-            }
+            //Popups opened by a command stay open
+            app.popup = Popup::None;
+            command::run_line(app, line.clone(), net_tx).await;
             cursor::reset_cursor(app);
             app.prompt.clear();
             app.input_mode.clear();
             app.input_mode.push(Span::from("N"));
-            app.popup = Popup::None;
         }
     }
 }
 
+// Buffer by its number in the overview and sidebar, like /swap
+fn switch_to_number(app: &mut App, number: usize) {
+    if let Some((server_id, channel_id)) = command::buffer_list(app).get(number).cloned() {
+        app.switch_buffer(&server_id, &channel_id);
+    }
+}
@@ -0,0 +1,197 @@
+// isupport.rs
+use std::collections::BTreeMap;
+use crate::app::ServerData;
+use crate::mode::ModeTable;
+use crate::casemap::CaseMapping;
+
+// RPL_ISUPPORT (005) features, RFC 1459 defaults until the server sends its own
+#[derive(Debug, Clone)]
+pub struct ISupport {
+    pub chantypes: String,
+    pub casemapping: CaseMapping,
+    pub nicklen: Option<usize>,
+    pub topiclen: Option<usize>,
+    pub network: Option<String>,
+    // Mode changes with an argument allowed in one MODE line
+    pub modes: Option<usize>,
+    // Command -> max targets, None is unlimited
+    pub targmax: BTreeMap<String, Option<usize>>,
+    // Every token as sent, value None for plain flags
+    pub tokens: BTreeMap<String, Option<String>>,
+}
+
+impl Default for ISupport {
+    fn default() -> Self {
+        Self {
+            chantypes: "#&".to_string(),
+            casemapping: CaseMapping::Rfc1459,
+            nicklen: None,
+            topiclen: None,
+            network: None,
+            modes: Some(3),
+            targmax: BTreeMap::new(),
+            tokens: BTreeMap::new(),
+        }
+    }
+}
+
+impl ISupport {
+    pub fn is_channel(&self, name: &str) -> bool {
+        name.chars().next().is_some_and(|c| self.chantypes.contains(c))
+    }
+}
+
+// The tokens of a 005 line, between our nick and the trailing "are supported by this server"
+pub fn parse(server: &mut ServerData, tokens: &[String]) {
+    for token in tokens {
+        // "-TOKEN" takes back an earlier one
+        if let Some(name) = token.strip_prefix('-') {
+            reset(server, name);
+            continue;
+        }
+        let (name, value) = match token.split_once('=') {
+            Some((n, v)) => (n, Some(unescape(v))),
+            None => (token.as_str(), None),
+        };
+        let number = value.as_deref().and_then(|v| v.parse::<usize>().ok());
+        let features = &mut server.isupport;
+        match name {
+            "CHANTYPES" => features.chantypes = value.clone().unwrap_or_default(),
+            "CASEMAPPING" => features.casemapping = CaseMapping::parse(value.as_deref().unwrap_or("rfc1459")),
+            "NICKLEN" => features.nicklen = number,
+            "TOPICLEN" => features.topiclen = number,
+            "NETWORK" => features.network = value.clone(),
+            "MODES" => features.modes = number,
+            "TARGMAX" => {
+                features.targmax = value.as_deref().unwrap_or("").split(',')
+                    .filter_map(|t| t.split_once(':'))
+                    .map(|(cmd, max)| (cmd.to_ascii_uppercase(), max.parse::<usize>().ok()))
+                    .collect();
+            }
+            "PREFIX" => server.mode_table.parse_prefix(value.as_deref().unwrap_or("")),
+            "CHANMODES" => server.mode_table.parse_chanmodes(value.as_deref().unwrap_or("")),
+            _ => {}
+        }
+        server.isupport.tokens.insert(name.to_string(), value);
+    }
+    server.channels.set_casemapping(server.isupport.casemapping);
+}
+
+fn reset(server: &mut ServerData, name: &str) {
+    let default = ISupport::default();
+    match name {
+        "CHANTYPES" => server.isupport.chantypes = default.chantypes,
+        "CASEMAPPING" => server.isupport.casemapping = default.casemapping,
+        "NICKLEN" => server.isupport.nicklen = default.nicklen,
+        "TOPICLEN" => server.isupport.topiclen = default.topiclen,
+        "NETWORK" => server.isupport.network = default.network,
+        "MODES" => server.isupport.modes = default.modes,
+        "TARGMAX" => server.isupport.targmax = default.targmax,
+        "PREFIX" => server.mode_table.prefix = ModeTable::default().prefix,
+        "CHANMODES" => server.mode_table.chanmodes = ModeTable::default().chanmodes,
+        _ => {}
+    }
+    server.isupport.tokens.remove(name);
+    server.channels.set_casemapping(server.isupport.casemapping);
+}
+
+// Values escape spaces and such as \x20
+fn unescape(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(pos) = rest.find("\\x") {
+        out.push_str(&rest[..pos]);
+        let hex = rest.get(pos + 2..pos + 4);
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push_str("\\x");
+                rest = &rest[pos + 2..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::ChannelData;
+
+    fn tokens(line: &str) -> Vec<String> {
+        line.split(' ').map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn prefix_and_chanmodes() {
+        let mut server = ServerData::default();
+        parse(&mut server, &tokens("PREFIX=(ov)@+ CHANMODES=beI,k,l,imnpst"));
+        assert_eq!(server.mode_table.prefix, vec![('o', '@'), ('v', '+')]);
+        assert_eq!(server.mode_table.chanmodes[1], "k");
+        assert_eq!(server.isupport.tokens.get("PREFIX"), Some(&Some("(ov)@+".to_string())));
+    }
+
+    #[test]
+    fn chantypes() {
+        let mut server = ServerData::default();
+        assert!(server.isupport.is_channel("&local"));
+        parse(&mut server, &tokens("CHANTYPES=#"));
+        assert!(server.isupport.is_channel("#rust"));
+        assert!(!server.isupport.is_channel("&local"));
+        // An empty value means the server has no channels at all
+        parse(&mut server, &tokens("CHANTYPES="));
+        assert!(!server.isupport.is_channel("#rust"));
+    }
+
+    #[test]
+    fn casemapping_rekeys_the_buffers() {
+        let mut server = ServerData::default();
+        parse(&mut server, &tokens("CASEMAPPING=ascii"));
+        assert_eq!(server.isupport.casemapping, CaseMapping::Ascii);
+        server.channels.insert("#foo[]".to_string(), ChannelData::default());
+        assert!(!server.channels.contains_key("#FOO{}"));
+        parse(&mut server, &tokens("CASEMAPPING=rfc1459"));
+        assert!(server.channels.contains_key("#FOO{}"));
+        parse(&mut server, &tokens("CASEMAPPING=strict-rfc1459"));
+        assert_eq!(server.isupport.casemapping, CaseMapping::StrictRfc1459);
+    }
+
+    #[test]
+    fn numbers_flags_and_targmax() {
+        let mut server = ServerData::default();
+        parse(&mut server, &tokens("NICKLEN=30 MODES SAFELIST TARGMAX=PRIVMSG:4,kick:1,JOIN:"));
+        assert_eq!(server.isupport.nicklen, Some(30));
+        // MODES without a value has no limit
+        assert_eq!(server.isupport.modes, None);
+        assert_eq!(server.isupport.tokens.get("SAFELIST"), Some(&None));
+        assert_eq!(server.isupport.targmax.get("PRIVMSG"), Some(&Some(4)));
+        assert_eq!(server.isupport.targmax.get("KICK"), Some(&Some(1)));
+        assert_eq!(server.isupport.targmax.get("JOIN"), Some(&None));
+    }
+
+    #[test]
+    fn negated_token_restores_the_default() {
+        let mut server = ServerData::default();
+        parse(&mut server, &tokens("NICKLEN=30 CHANTYPES=# CASEMAPPING=ascii PREFIX=(ov)@+"));
+        parse(&mut server, &tokens("-NICKLEN -CHANTYPES -CASEMAPPING -PREFIX"));
+        assert_eq!(server.isupport.nicklen, None);
+        assert_eq!(server.isupport.chantypes, "#&");
+        assert_eq!(server.isupport.casemapping, CaseMapping::Rfc1459);
+        assert_eq!(server.mode_table.prefix, ModeTable::default().prefix);
+        assert!(!server.isupport.tokens.contains_key("NICKLEN"));
+    }
+
+    #[test]
+    fn escaped_values() {
+        let mut server = ServerData::default();
+        parse(&mut server, &tokens(r"NETWORK=Example\x20Net\x3DIRC"));
+        assert_eq!(server.isupport.network.as_deref(), Some("Example Net=IRC"));
+        assert_eq!(unescape(r"a\x5Cb"), r"a\b");
+        // Broken escapes are kept as they are
+        assert_eq!(unescape(r"bad\xZZ end\x2"), r"bad\xZZ end\x2");
+    }
+}
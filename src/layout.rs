@@ -0,0 +1,443 @@
+// layout.rs
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use ratatui::layout::Rect;
+
+// Vertical puts panes side by side, horizontal stacks them, like "/window split v" and "h"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Dir {
+    Horizontal,
+    Vertical,
+}
+
+// Where /window move sends the focused buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pane {
+    pub server: String,
+    pub channel: String,
+}
+
+impl Pane {
+    pub fn new(server: &str, channel: &str) -> Pane {
+        Pane { server: server.to_string(), channel: channel.to_string() }
+    }
+
+    fn status() -> Pane {
+        Pane::new("System", "Status")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Node {
+    Pane(Pane),
+    // ratio is the percentage of the area the first child gets
+    Split { dir: Dir, ratio: u16, first: Box<Node>, second: Box<Node> },
+}
+
+impl Node {
+    fn count(&self) -> usize {
+        match self {
+            Node::Pane(_) => 1,
+            Node::Split { first, second, .. } => first.count() + second.count(),
+        }
+    }
+
+    fn collect<'a>(&'a self, panes: &mut Vec<&'a Pane>) {
+        match self {
+            Node::Pane(p) => panes.push(p),
+            Node::Split { first, second, .. } => {
+                first.collect(panes);
+                second.collect(panes);
+            }
+        }
+    }
+
+    fn collect_mut<'a>(&'a mut self, panes: &mut Vec<&'a mut Pane>) {
+        match self {
+            Node::Pane(p) => panes.push(p),
+            Node::Split { first, second, .. } => {
+                first.collect_mut(panes);
+                second.collect_mut(panes);
+            }
+        }
+    }
+
+    // Replaces pane number index with what f makes of it
+    fn replace_pane(&mut self, index: usize, f: &mut dyn FnMut(Node) -> Node) -> bool {
+        match self {
+            Node::Pane(_) if index == 0 => {
+                let old = std::mem::replace(self, Node::Pane(Pane::status()));
+                *self = f(old);
+                true
+            }
+            Node::Pane(_) => false,
+            Node::Split { first, second, .. } => {
+                let in_first = first.count();
+                if index < in_first {
+                    first.replace_pane(index, f)
+                } else {
+                    second.replace_pane(index - in_first, f)
+                }
+            }
+        }
+    }
+
+    // Drops pane number index, its sibling takes the place of the split. Some(true) when it was a first child
+    fn remove_pane(&mut self, index: usize) -> Option<bool> {
+        let Node::Split { first, second, .. } = self else {
+            return None;
+        };
+        let in_first = first.count();
+        if index < in_first {
+            if matches!(**first, Node::Pane(_)) {
+                let sibling = std::mem::replace(&mut **second, Node::Pane(Pane::status()));
+                *self = sibling;
+                return Some(true);
+            }
+            first.remove_pane(index)
+        } else {
+            if matches!(**second, Node::Pane(_)) {
+                let sibling = std::mem::replace(&mut **first, Node::Pane(Pane::status()));
+                *self = sibling;
+                return Some(false);
+            }
+            second.remove_pane(index - in_first)
+        }
+    }
+
+    // Moves the divider of the innermost split in dir around pane number index, true once done
+    fn resize(&mut self, index: usize, dir: Dir, delta: i16) -> bool {
+        let Node::Split { dir: split_dir, ratio, first, second } = self else {
+            return false;
+        };
+        let in_first = first.count();
+        let done = if index < in_first {
+            first.resize(index, dir, delta)
+        } else {
+            second.resize(index - in_first, dir, delta)
+        };
+        if done || *split_dir != dir {
+            return done;
+        }
+        *ratio = (*ratio as i16 + delta).clamp(10, 90) as u16;
+        true
+    }
+
+    fn areas(&self, area: Rect, rects: &mut Vec<Rect>) {
+        match self {
+            Node::Pane(_) => rects.push(area),
+            Node::Split { dir, ratio, first, second } => {
+                let (a, b) = match dir {
+                    Dir::Vertical => {
+                        let w = (area.width as u32 * *ratio as u32 / 100) as u16;
+                        (Rect { width: w, ..area }, Rect { x: area.x + w, width: area.width - w, ..area })
+                    }
+                    Dir::Horizontal => {
+                        let h = (area.height as u32 * *ratio as u32 / 100) as u16;
+                        (Rect { height: h, ..area }, Rect { y: area.y + h, height: area.height - h, ..area })
+                    }
+                };
+                first.areas(a, rects);
+                second.areas(b, rects);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowLayout {
+    pub root: Node,
+    // Pane with the active buffer, counted in drawing order
+    pub focus: usize,
+    // Active buffer last written into the focused pane
+    #[serde(skip)]
+    synced: (String, String),
+    // Pane areas from the last draw, in drawing order
+    #[serde(skip)]
+    pub rects: Vec<Rect>,
+}
+
+impl Default for WindowLayout {
+    fn default() -> Self {
+        Self {
+            root: Node::Pane(Pane::status()),
+            focus: 0,
+            synced: ("System".to_string(), "Status".to_string()),
+            rects: Vec::new(),
+        }
+    }
+}
+
+impl WindowLayout {
+    // Two panes side by side, what /split sets up
+    pub fn two(left: Pane, right: Pane) -> WindowLayout {
+        WindowLayout {
+            synced: (left.server.clone(), left.channel.clone()),
+            root: Node::Split { dir: Dir::Vertical, ratio: 50, first: Box::new(Node::Pane(left)), second: Box::new(Node::Pane(right)) },
+            ..Default::default()
+        }
+    }
+
+    pub fn is_split(&self) -> bool {
+        matches!(self.root, Node::Split { .. })
+    }
+
+    pub fn panes(&self) -> Vec<&Pane> {
+        let mut panes = Vec::new();
+        self.root.collect(&mut panes);
+        panes
+    }
+
+    fn panes_mut(&mut self) -> Vec<&mut Pane> {
+        let mut panes = Vec::new();
+        self.root.collect_mut(&mut panes);
+        panes
+    }
+
+    // The focused pane follows the active buffer, whatever changed it
+    pub fn sync(&mut self, server: &str, channel: &str) {
+        if self.synced.0 == server && self.synced.1 == channel {
+            return;
+        }
+        self.synced = (server.to_string(), channel.to_string());
+        let focus = self.focus;
+        if let Some(pane) = self.panes_mut().into_iter().nth(focus) {
+            *pane = Pane::new(server, channel);
+        }
+    }
+
+    // Focuses a pane, its buffer is the one to make active
+    pub fn focus_pane(&mut self, index: usize) -> Option<Pane> {
+        let pane = self.panes().get(index).map(|p| (*p).clone())?;
+        self.focus = index;
+        self.synced = (pane.server.clone(), pane.channel.clone());
+        Some(pane)
+    }
+
+    // Pane showing a buffer, the focused one first
+    pub fn find(&self, server: &str, channel: &str) -> Option<usize> {
+        let panes = self.panes();
+        let matches = |p: &&Pane| p.server == server && p.channel == channel;
+        if panes.get(self.focus).is_some_and(matches) {
+            return Some(self.focus);
+        }
+        panes.iter().position(matches)
+    }
+
+    // Splits the focused pane, the new pane shows pane and gets the focus
+    pub fn split(&mut self, dir: Dir, pane: Pane) {
+        let new = pane.clone();
+        self.root.replace_pane(self.focus, &mut |old| Node::Split { dir, ratio: 50, first: Box::new(old), second: Box::new(Node::Pane(new.clone())) });
+        self.focus += 1;
+        self.synced = (pane.server, pane.channel);
+    }
+
+    // Closes the focused pane, false when it is the only one
+    pub fn close(&mut self) -> bool {
+        match self.root.remove_pane(self.focus) {
+            Some(was_first) => {
+                if !was_first {
+                    self.focus = self.focus.saturating_sub(1);
+                }
+                self.focus = self.focus.min(self.root.count() - 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn resize(&mut self, dir: Dir, delta: i16) {
+        self.root.resize(self.focus, dir, delta);
+    }
+
+    // Swaps the focused buffer with the nearest pane on that side, the focus moves along with it
+    pub fn move_pane(&mut self, side: Side) -> bool {
+        let from = match self.rects.get(self.focus) {
+            Some(r) => *r,
+            None => return false,
+        };
+        let overlaps = |a0: u16, a1: u16, b0: u16, b1: u16| a0 < b1 && b0 < a1;
+        let target = self.rects.iter().enumerate()
+            .filter(|(i, _)| *i != self.focus)
+            .filter_map(|(i, r)| {
+                let distance = match side {
+                    Side::Left if r.right() <= from.x && overlaps(r.y, r.bottom(), from.y, from.bottom()) => from.x - r.right(),
+                    Side::Right if r.x >= from.right() && overlaps(r.y, r.bottom(), from.y, from.bottom()) => r.x - from.right(),
+                    Side::Up if r.bottom() <= from.y && overlaps(r.x, r.right(), from.x, from.right()) => from.y - r.bottom(),
+                    Side::Down if r.y >= from.bottom() && overlaps(r.x, r.right(), from.x, from.right()) => r.y - from.bottom(),
+                    _ => return None,
+                };
+                Some((distance, i))
+            })
+            .min()
+            .map(|(_, i)| i);
+        let target = match target {
+            Some(t) => t,
+            None => return false,
+        };
+        let focus = self.focus;
+        let mut panes = self.panes_mut();
+        let moved = panes[focus].clone();
+        let other = std::mem::replace(&mut *panes[target], moved);
+        *panes[focus] = other;
+        self.focus = target;
+        true
+    }
+
+    // Panes showing a closed buffer, or any buffer of a closed server, go back to the Status buffer
+    pub fn buffer_closed(&mut self, server: &str, channel: Option<&str>) {
+        for pane in self.panes_mut() {
+            if pane.server == server && channel.is_none_or(|c| pane.channel == c) {
+                *pane = Pane::status();
+            }
+        }
+    }
+
+    // Works out every pane's area, kept for the next key press and for wrapping
+    pub fn layout(&mut self, area: Rect) {
+        self.rects.clear();
+        self.root.areas(area, &mut self.rects);
+    }
+}
+
+fn layout_path() -> PathBuf {
+    let mut path = dirs_next::data_dir().unwrap_or_default();
+    path.push("rustychat/layout.toml");
+    path
+}
+
+// The layout saved at the last quit, a single pane is not worth restoring
+pub fn load() -> Option<WindowLayout> {
+    let text = fs::read_to_string(layout_path()).ok()?;
+    let mut layout: WindowLayout = toml::from_str(&text).ok()?;
+    if !layout.is_split() {
+        return None;
+    }
+    layout.focus = layout.focus.min(layout.root.count() - 1);
+    // The client starts in the Status buffer, so that is what the focused pane shows. The other panes
+    // stay empty until their servers connect and rejoin
+    let focus = layout.focus;
+    if let Some(pane) = layout.panes_mut().into_iter().nth(focus) {
+        *pane = Pane::status();
+    }
+    layout.synced = ("System".to_string(), "Status".to_string());
+    Some(layout)
+}
+
+pub fn save(layout: &WindowLayout) {
+    let path = layout_path();
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(text) = toml::to_string(layout) {
+        let _ = fs::write(path, text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(layout: &WindowLayout) -> Vec<&str> {
+        layout.panes().iter().map(|p| p.channel.as_str()).collect()
+    }
+
+    fn area() -> Rect {
+        Rect { x: 0, y: 0, width: 100, height: 40 }
+    }
+
+    #[test]
+    fn split_and_close() {
+        let mut layout = WindowLayout::default();
+        assert!(!layout.close());
+        layout.split(Dir::Vertical, Pane::new("srv", "#a"));
+        layout.split(Dir::Horizontal, Pane::new("srv", "#b"));
+        assert_eq!(channels(&layout), vec!["Status", "#a", "#b"]);
+        assert_eq!(layout.focus, 2);
+
+        // Closing a second child focuses its sibling
+        assert!(layout.close());
+        assert_eq!(channels(&layout), vec!["Status", "#a"]);
+        assert_eq!(layout.focus, 1);
+
+        // Closing a first child leaves the focus on the pane that took its place
+        layout.focus_pane(0);
+        assert!(layout.close());
+        assert_eq!(channels(&layout), vec!["#a"]);
+        assert_eq!(layout.focus, 0);
+        assert!(!layout.is_split());
+    }
+
+    #[test]
+    fn sync_follows_focus() {
+        let mut layout = WindowLayout::two(Pane::new("srv", "#a"), Pane::new("srv", "#b"));
+        assert_eq!(layout.focus_pane(1), Some(Pane::new("srv", "#b")));
+        layout.sync("srv", "#c");
+        assert_eq!(channels(&layout), vec!["#a", "#c"]);
+        assert_eq!(layout.find("srv", "#a"), Some(0));
+        assert_eq!(layout.focus_pane(5), None);
+    }
+
+    #[test]
+    fn resize_innermost_matching_split() {
+        // Status | (#a over #b), focus on #b
+        let mut layout = WindowLayout::default();
+        layout.split(Dir::Vertical, Pane::new("srv", "#a"));
+        layout.split(Dir::Horizontal, Pane::new("srv", "#b"));
+
+        layout.resize(Dir::Vertical, -20);
+        layout.resize(Dir::Horizontal, 10);
+        layout.layout(area());
+        assert_eq!(layout.rects[0].width, 30);
+        assert_eq!(layout.rects[1].height, 24);
+
+        // Ratios stay within 10 and 90 percent
+        layout.resize(Dir::Vertical, -100);
+        layout.layout(area());
+        assert_eq!(layout.rects[0].width, 10);
+        assert_eq!(layout.rects[2].width, 90);
+    }
+
+    #[test]
+    fn move_pane_swaps_neighbours() {
+        // Status | (#a over #b)
+        let mut layout = WindowLayout::default();
+        layout.split(Dir::Vertical, Pane::new("srv", "#a"));
+        layout.split(Dir::Horizontal, Pane::new("srv", "#b"));
+        layout.layout(area());
+
+        assert!(layout.move_pane(Side::Up));
+        assert_eq!(channels(&layout), vec!["Status", "#b", "#a"]);
+        assert_eq!(layout.focus, 1);
+
+        assert!(layout.move_pane(Side::Left));
+        assert_eq!(channels(&layout), vec!["#b", "Status", "#a"]);
+        assert_eq!(layout.focus, 0);
+
+        // Nothing further that way
+        assert!(!layout.move_pane(Side::Left));
+        assert!(!layout.move_pane(Side::Up));
+        assert_eq!(layout.focus, 0);
+    }
+
+    #[test]
+    fn closed_buffers_fall_back_to_status() {
+        let mut layout = WindowLayout::two(Pane::new("srv", "#a"), Pane::new("srv", "#b"));
+        layout.split(Dir::Horizontal, Pane::new("other", "#a"));
+
+        layout.buffer_closed("srv", Some("#a"));
+        assert_eq!(layout.panes(), vec![&Pane::status(), &Pane::new("other", "#a"), &Pane::new("srv", "#b")]);
+
+        layout.buffer_closed("other", None);
+        assert_eq!(layout.panes(), vec![&Pane::status(), &Pane::status(), &Pane::new("srv", "#b")]);
+    }
+}
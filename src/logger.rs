@@ -0,0 +1,278 @@
+// logger.rs
+use tokio::sync::mpsc;
+use tokio::io::AsyncWriteExt;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use tokio::task::JoinHandle;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::textstyle;
+use crate::app::{ChatLine, LineKind};
+use crate::casemap::CaseMapping;
+
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    pub enabled: bool,
+    pub strip_formatting: bool,
+    // Channels (or "Status") that are never written to disk
+    pub exclude: Vec<String>,
+    pub dir: PathBuf,
+    // Lines read back from the log when a buffer is opened, 0 turns it off
+    pub backlog: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        let mut dir = dirs_next::data_dir().unwrap_or_default();
+        dir.push("rustychat/logs");
+        Self { enabled: false, strip_formatting: true, exclude: Vec::new(), dir, backlog: 50 }
+    }
+}
+
+struct LogEntry {
+    path: PathBuf,
+    line: String,
+}
+
+enum LogJob {
+    Write(LogEntry),
+    // Queued behind the writes before it, so a new buffer never reads back the line that opened it
+    Backlog { server: String, channel: String, dir: PathBuf, limit: usize, reply: mpsc::UnboundedSender<Backlog> },
+}
+
+// Logged lines for a buffer that was just opened
+pub struct Backlog {
+    pub server: String,
+    pub channel: String,
+    pub lines: Vec<ChatLine>,
+}
+
+// Lines are handed to a background task, so disk io never blocks the ui loop
+#[derive(Default)]
+pub struct Logger {
+    pub settings: LogSettings,
+    tx: Option<mpsc::UnboundedSender<LogJob>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Logger {
+    // The task also runs with logging off, old logs can still be read back
+    pub fn start(settings: LogSettings) -> Logger {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_loop(rx));
+        Logger { settings, tx: Some(tx), task: Some(task) }
+    }
+
+    // Closes the queue and waits until everything sent so far is on disk
+    pub async fn shutdown(&mut self) {
+        self.tx = None;
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+
+    pub fn log(&self, server_id: &str, channel_id: &str, chat_line: &ChatLine, casemap: CaseMapping) {
+        let tx = match &self.tx {
+            Some(tx) if self.settings.enabled => tx,
+            _ => return,
+        };
+        if self.is_excluded(channel_id, casemap) {
+            return;
+        }
+        let text = if self.settings.strip_formatting { textstyle::strip_formatting(&chat_line.text) } else { chat_line.text.clone() };
+        let stamp = chat_line.time.format("%Y-%m-%d %H:%M:%S");
+        let line = match chat_line.kind {
+            LineKind::Action => format!("[{}] * {} {}\n", stamp, chat_line.nick, text),
+            _ => format!("[{}] <{}> {}\n", stamp, chat_line.nick, text),
+        };
+        let _ = tx.send(LogJob::Write(LogEntry { path: self.log_path(server_id, channel_id, chat_line.time, casemap), line }));
+    }
+
+    // Asks for the last lines of the channel log, they come back on reply if there are any
+    pub fn request_backlog(&self, server_id: &str, channel_id: &str, casemap: CaseMapping, reply: &mpsc::UnboundedSender<Backlog>) {
+        if self.settings.backlog == 0 || self.is_excluded(channel_id, casemap) {
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(LogJob::Backlog {
+                server: server_id.to_string(),
+                channel: channel_id.to_string(),
+                dir: self.channel_dir(server_id, channel_id, casemap),
+                limit: self.settings.backlog,
+                reply: reply.clone(),
+            });
+        }
+    }
+
+    pub fn is_excluded(&self, channel_id: &str, casemap: CaseMapping) -> bool {
+        self.settings.exclude.iter().any(|c| casemap.eq(c, channel_id))
+    }
+
+    // <dir>/<server>/<channel>/YYYY-MM-DD.log
+    pub fn log_path(&self, server_id: &str, channel_id: &str, time: DateTime<Local>, casemap: CaseMapping) -> PathBuf {
+        let mut path = self.channel_dir(server_id, channel_id, casemap);
+        path.push(format!("{}.log", time.format("%Y-%m-%d")));
+        path
+    }
+
+    // Folded the way the server compares names, so #foo[] and #FOO{} share a directory under rfc1459
+    pub fn channel_dir(&self, server_id: &str, channel_id: &str, casemap: CaseMapping) -> PathBuf {
+        let mut path = self.settings.dir.clone();
+        path.push(safe_name(server_id));
+        path.push(safe_name(&casemap.normalize(channel_id)));
+        path
+    }
+}
+
+// "[YYYY-MM-DD HH:MM:SS] <nick> text" back into a dimmed backlog line
+fn parse_line(line: &str) -> Option<ChatLine> {
+    let (stamp, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    // "* nick text" for actions, kept whole behind a "*" nick
+    let (nick, text) = match rest.strip_prefix('<') {
+        Some(msg) => msg.split_once("> ")?,
+        None => ("*", rest.strip_prefix("* ")?),
+    };
+    let naive = NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S").ok()?;
+    let time = Local.from_local_datetime(&naive).earliest()?;
+    Some(ChatLine { kind: LineKind::Backlog, ..ChatLine::at(time, nick, text) })
+}
+
+// Keep names from escaping the log directory
+fn safe_name(name: &str) -> String {
+    let cleaned: String = name.chars().map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c }).collect();
+    match cleaned.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => cleaned,
+    }
+}
+
+// Last lines of the channel log, newest file first until enough are found
+fn read_backlog(dir: &Path, limit: usize) -> Vec<ChatLine> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().is_some_and(|x| x == "log")).collect(),
+        Err(_) => return Vec::new(),
+    };
+    // YYYY-MM-DD names sort by date
+    files.sort();
+
+    let mut lines: Vec<ChatLine> = Vec::new();
+    for file in files.iter().rev() {
+        let take = limit - lines.len();
+        let mut day: Vec<ChatLine> = tail_lines(file, take).iter().filter_map(|l| parse_line(l)).collect();
+        if day.len() > take {
+            day = day.split_off(day.len() - take);
+        }
+        day.append(&mut lines);
+        lines = day;
+        if lines.len() >= limit {
+            break;
+        }
+    }
+    lines
+}
+
+// Up to count whole lines from the end of a file, read backwards a block at a time
+fn tail_lines(path: &Path, count: usize) -> Vec<String> {
+    const BLOCK: u64 = 8192;
+    let mut file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+    let mut pos = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut buf: Vec<u8> = Vec::new();
+    let mut newlines = 0;
+    // One newline more than lines wanted, so the first line kept is a whole one
+    while pos > 0 && newlines <= count {
+        let step = BLOCK.min(pos);
+        pos -= step;
+        let mut chunk = vec![0; step as usize];
+        if file.seek(SeekFrom::Start(pos)).is_err() || file.read_exact(&mut chunk).is_err() {
+            return Vec::new();
+        }
+        newlines += chunk.iter().filter(|b| **b == b'\n').count();
+        chunk.append(&mut buf);
+        buf = chunk;
+    }
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines: Vec<&str> = text.lines().collect();
+    if pos > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let skip = lines.len().saturating_sub(count);
+    lines[skip..].iter().map(|l| l.to_string()).collect()
+}
+
+async fn write_loop(mut rx: mpsc::UnboundedReceiver<LogJob>) {
+    let mut files: HashMap<PathBuf, tokio::fs::File> = HashMap::new();
+    while let Some(job) = rx.recv().await {
+        // Everything already queued goes out as one batch, flushed before waiting again
+        let mut batch = vec![job];
+        while let Ok(job) = rx.try_recv() {
+            batch.push(job);
+        }
+        for job in batch {
+            match job {
+                LogJob::Write(entry) => write_entry(&mut files, entry).await,
+                LogJob::Backlog { server, channel, dir, limit, reply } => {
+                    // Earlier lines of this channel have to be on disk before reading it back
+                    flush_all(&mut files).await;
+                    let lines = tokio::task::spawn_blocking(move || read_backlog(&dir, limit)).await.unwrap_or_default();
+                    if !lines.is_empty() {
+                        let _ = reply.send(Backlog { server, channel, lines });
+                    }
+                }
+            }
+        }
+        flush_all(&mut files).await;
+    }
+}
+
+async fn write_entry(files: &mut HashMap<PathBuf, tokio::fs::File>, entry: LogEntry) {
+    if !files.contains_key(&entry.path) {
+        // A new day means a new file, the old handles are not needed anymore
+        if files.len() > 64 {
+            flush_all(files).await;
+            files.clear();
+        }
+        if let Some(parent) = entry.path.parent() {
+            let _ = tokio::fs::create_dir_all(parent).await;
+        }
+        match tokio::fs::OpenOptions::new().create(true).append(true).open(&entry.path).await {
+            Ok(file) => {
+                files.insert(entry.path.clone(), file);
+            }
+            Err(_) => return,
+        }
+    }
+    if let Some(file) = files.get_mut(&entry.path) {
+        let _ = file.write_all(entry.line.as_bytes()).await;
+    }
+}
+
+async fn flush_all(files: &mut HashMap<PathBuf, tokio::fs::File>) {
+    for file in files.values_mut() {
+        let _ = file.flush().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_reads_whole_lines_across_blocks() {
+        let path = std::env::temp_dir().join(format!("rustychat-test-{}-tail.log", std::process::id()));
+        let content: String = (0..2000).map(|i| format!("[2024-01-01 12:00:00] <nick> line {i}\n")).collect();
+        std::fs::write(&path, content).unwrap();
+        let few = tail_lines(&path, 3);
+        let many = tail_lines(&path, 1500);
+        let all = tail_lines(&path, 5000);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(few, vec!["[2024-01-01 12:00:00] <nick> line 1997", "[2024-01-01 12:00:00] <nick> line 1998", "[2024-01-01 12:00:00] <nick> line 1999"]);
+        assert_eq!(many.len(), 1500);
+        assert_eq!(many[0], "[2024-01-01 12:00:00] <nick> line 500");
+        assert_eq!(all.len(), 2000);
+        assert_eq!(parse_line(&all[0]).unwrap().text, "line 0");
+    }
+}
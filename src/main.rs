@@ -5,8 +5,23 @@ mod event;
 mod config;
 mod textstyle;
 mod cursor;
+mod message;
+mod cap;
+mod logger;
+mod search;
+mod complete;
+mod command;
+mod ctcp;
+mod mode;
+mod isupport;
+mod casemap;
+mod whois;
+mod userlist;
+mod chanlist;
+mod layout;
 use crossterm::{
     execute, 
+    event::{EnableMouseCapture, DisableMouseCapture},
     terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use std::io::{stdout, Result};
@@ -16,7 +31,7 @@ use crate::app::App;
 async fn main() -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = ratatui::prelude::CrosstermBackend::new(stdout);
     let mut terminal = ratatui::Terminal::new(backend)?;
 
@@ -24,7 +39,7 @@ async fn main() -> Result<()> {
     let appresult = App::default().run(&mut terminal).await;
 
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     let _ = terminal.show_cursor();
     appresult
 }
@@ -0,0 +1,279 @@
+// message.rs
+use std::fmt;
+
+// nick!user@host, or just a server name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Source {
+    pub fn parse(raw: &str) -> Source {
+        let (nick_user, host) = match raw.split_once('@') {
+            Some((nu, h)) => (nu, Some(h.to_string())),
+            None => (raw, None),
+        };
+        let (nick, user) = match nick_user.split_once('!') {
+            Some((n, u)) => (n, Some(u.to_string())),
+            None => (nick_user, None),
+        };
+        Source { nick: nick.to_string(), user, host }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.nick)?;
+        if let Some(user) = &self.user {
+            write!(f, "!{}", user)?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "@{}", host)?;
+        }
+        Ok(())
+    }
+}
+
+// A single irc line, RFC1459 with IRCv3 message tags
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub tags: Vec<(String, Option<String>)>,
+    pub source: Option<Source>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new(command: &str, params: &[&str]) -> IrcMessage {
+        IrcMessage {
+            tags: Vec::new(),
+            source: None,
+            command: command.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    // Returns None for empty lines or lines without a command
+    pub fn parse(line: &str) -> Option<IrcMessage> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        let mut msg = IrcMessage::default();
+
+        if let Some(tag_str) = rest.strip_prefix('@') {
+            let (tags, tail) = tag_str.split_once(' ').unwrap_or((tag_str, ""));
+            for tag in tags.split(';').filter(|t| !t.is_empty()) {
+                match tag.split_once('=') {
+                    Some((key, value)) => msg.tags.push((key.to_string(), Some(unescape_tag(value)))),
+                    None => msg.tags.push((tag.to_string(), None)),
+                }
+            }
+            rest = tail;
+        }
+        rest = rest.trim_start_matches(' ');
+
+        if let Some(src) = rest.strip_prefix(':') {
+            let (source, tail) = src.split_once(' ').unwrap_or((src, ""));
+            msg.source = Some(Source::parse(source));
+            rest = tail.trim_start_matches(' ');
+        }
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+        msg.command = command.to_ascii_uppercase();
+
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                msg.params.push(trailing.to_string());
+                break;
+            }
+            let (param, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            msg.params.push(param.to_string());
+            rest = tail;
+        }
+        Some(msg)
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| k == key).and_then(|(_, v)| v.as_deref())
+    }
+
+    // Param at index, empty if missing
+    pub fn param(&self, index: usize) -> &str {
+        self.params.get(index).map(|p| p.as_str()).unwrap_or("")
+    }
+
+    pub fn trailing(&self) -> &str {
+        self.params.last().map(|p| p.as_str()).unwrap_or("")
+    }
+
+    pub fn nick(&self) -> &str {
+        self.source.as_ref().map(|s| s.nick.as_str()).unwrap_or("")
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.command.len() == 3 && self.command.bytes().all(|b| b.is_ascii_digit())
+    }
+
+    // Params joined for display, numerics skip the first param as it is always our own nick
+    pub fn display_params(&self) -> String {
+        let skip = if self.is_numeric() { 1 } else { 0 };
+        self.params.iter().skip(skip).map(|p| p.as_str()).collect::<Vec<&str>>().join(" ")
+    }
+}
+
+impl fmt::Display for IrcMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter().map(|(k, v)| match v {
+                Some(v) => format!("{}={}", k, escape_tag(v)),
+                None => k.clone(),
+            }).collect();
+            write!(f, "@{} ", tags.join(";"))?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, ":{} ", source)?;
+        }
+        write!(f, "{}", self.command)?;
+        for (i, param) in self.params.iter().enumerate() {
+            let last = i == self.params.len() - 1;
+            if last && (param.is_empty() || param.contains(' ') || param.starts_with(':')) {
+                write!(f, " :{}", param)?;
+            } else {
+                write!(f, " {}", param)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        // A lone trailing backslash is dropped
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+fn escape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_tag_values() {
+        let msg = IrcMessage::parse(r"@a=one\:two;b=x\sy;c=back\\slash;d=end\;e :nick PRIVMSG #a :hi").unwrap();
+        assert_eq!(msg.tag("a"), Some("one;two"));
+        assert_eq!(msg.tag("b"), Some("x y"));
+        assert_eq!(msg.tag("c"), Some(r"back\slash"));
+        assert_eq!(msg.tag("d"), Some("end"));
+        assert_eq!(msg.tags[4], ("e".to_string(), None));
+        assert_eq!(msg.tag("e"), None);
+    }
+
+    #[test]
+    fn no_prefix() {
+        let msg = IrcMessage::parse("PING :irc.example.org\r\n").unwrap();
+        assert!(msg.source.is_none());
+        assert!(msg.tags.is_empty());
+        assert_eq!(msg.command, "PING");
+        assert_eq!(msg.params, vec!["irc.example.org"]);
+    }
+
+    #[test]
+    fn source_parts() {
+        let msg = IrcMessage::parse(":nick!user@host.example JOIN #rust").unwrap();
+        let source = msg.source.unwrap();
+        assert_eq!(source.nick, "nick");
+        assert_eq!(source.user.as_deref(), Some("user"));
+        assert_eq!(source.host.as_deref(), Some("host.example"));
+    }
+
+    #[test]
+    fn hash_in_middle_and_colon_in_trailing() {
+        let msg = IrcMessage::parse(":srv 322 me #rust 42 :topic: with a colon :) here").unwrap();
+        assert_eq!(msg.params, vec!["me", "#rust", "42", "topic: with a colon :) here"]);
+        let msg = IrcMessage::parse("MODE #a#b +o nick").unwrap();
+        assert_eq!(msg.params, vec!["#a#b", "+o", "nick"]);
+    }
+
+    #[test]
+    fn empty_trailing() {
+        let msg = IrcMessage::parse("PRIVMSG #a :").unwrap();
+        assert_eq!(msg.params, vec!["#a", ""]);
+        assert_eq!(msg.trailing(), "");
+    }
+
+    #[test]
+    fn multiple_spaces() {
+        let msg = IrcMessage::parse(":nick   PRIVMSG   #a    b   :hello  there").unwrap();
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(msg.params, vec!["#a", "b", "hello  there"]);
+    }
+
+    #[test]
+    fn empty_lines() {
+        assert!(IrcMessage::parse("").is_none());
+        assert!(IrcMessage::parse("\r\n").is_none());
+        assert!(IrcMessage::parse("@a=b ").is_none());
+        assert!(IrcMessage::parse(":nick").is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let lines = [
+            "@time=2024-01-01T00:00:00.000Z;msgid=a\\sb\\:c :nick!user@host PRIVMSG #a :hello there",
+            ":srv 001 me :Welcome to the network",
+            "PRIVMSG #a :",
+            "PRIVMSG #a ::starts with a colon",
+            "MODE #a +o nick",
+            "@draft/flag :nick TAGMSG #a",
+        ];
+        for line in lines {
+            let msg = IrcMessage::parse(line).unwrap();
+            assert_eq!(msg.to_string(), line);
+            assert_eq!(IrcMessage::parse(&msg.to_string()).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn last_param_gets_colon() {
+        assert_eq!(IrcMessage::new("PRIVMSG", &["#a", "two words"]).to_string(), "PRIVMSG #a :two words");
+        assert_eq!(IrcMessage::new("PRIVMSG", &["#a", ":)"]).to_string(), "PRIVMSG #a ::)");
+        assert_eq!(IrcMessage::new("PRIVMSG", &["#a", ""]).to_string(), "PRIVMSG #a :");
+        assert_eq!(IrcMessage::new("JOIN", &["#a"]).to_string(), "JOIN #a");
+    }
+}
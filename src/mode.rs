@@ -0,0 +1,235 @@
+// mode.rs
+use std::collections::BTreeMap;
+
+// A channel member, prefixes holds the mode symbols (~&@%+) highest rank first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct User {
+    pub nick: String,
+    pub prefixes: String,
+    // From away-notify
+    pub away: bool,
+}
+
+impl User {
+    pub fn new(nick: &str) -> User {
+        User { nick: nick.to_string(), ..Default::default() }
+    }
+
+    pub fn highest(&self) -> Option<char> {
+        self.prefixes.chars().next()
+    }
+
+    // Nick with its highest prefix, "@nick"
+    pub fn display(&self) -> String {
+        match self.highest() {
+            Some(p) => format!("{}{}", p, self.nick),
+            None => self.nick.clone(),
+        }
+    }
+}
+
+// One +x or -x from a MODE line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    pub set: bool,
+    pub mode: char,
+    pub arg: Option<String>,
+}
+
+// How the server's modes behave, from the PREFIX and CHANMODES ISUPPORT tokens
+#[derive(Debug, Clone)]
+pub struct ModeTable {
+    // (mode letter, symbol) highest rank first, PREFIX=(qaohv)~&@%+
+    pub prefix: Vec<(char, char)>,
+    // CHANMODES=A,B,C,D: lists, always an argument, argument when set, flags
+    pub chanmodes: [String; 4],
+}
+
+impl Default for ModeTable {
+    fn default() -> Self {
+        Self {
+            prefix: vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')],
+            chanmodes: ["beI".to_string(), "k".to_string(), "l".to_string(), "imnpst".to_string()],
+        }
+    }
+}
+
+impl ModeTable {
+    // "(ov)@+"
+    pub fn parse_prefix(&mut self, value: &str) {
+        if let Some((modes, symbols)) = value.strip_prefix('(').and_then(|v| v.split_once(')')) {
+            self.prefix = modes.chars().zip(symbols.chars()).collect();
+        }
+    }
+
+    // "beI,k,l,imnpst"
+    pub fn parse_chanmodes(&mut self, value: &str) {
+        for (i, group) in value.split(',').take(4).enumerate() {
+            self.chanmodes[i] = group.to_string();
+        }
+    }
+
+    pub fn symbol(&self, mode: char) -> Option<char> {
+        self.prefix.iter().find(|(m, _)| *m == mode).map(|(_, s)| *s)
+    }
+
+    // 0 is the highest rank, plain users come last
+    pub fn rank(&self, user: &User) -> usize {
+        user.highest()
+            .and_then(|h| self.prefix.iter().position(|(_, s)| *s == h))
+            .unwrap_or(self.prefix.len())
+    }
+
+    // Sort key for user lists, by rank then nick
+    pub fn sort_key(&self, user: &User) -> (usize, String) {
+        (self.rank(user), user.nick.to_lowercase())
+    }
+
+    // A name from NAMES, "@+nick" with multi-prefix or "nick!user@host" with userhost-in-names
+    pub fn parse_user(&self, name: &str) -> User {
+        let nick = name.trim_start_matches(|c| self.prefix.iter().any(|(_, s)| *s == c));
+        let prefixes = &name[..name.len() - nick.len()];
+        let nick = nick.split('!').next().unwrap_or(nick);
+        let mut user = User::new(nick);
+        for symbol in prefixes.chars() {
+            self.add_prefix(&mut user, symbol);
+        }
+        user
+    }
+
+    // Keeps the prefixes in rank order
+    pub fn add_prefix(&self, user: &mut User, symbol: char) {
+        if user.prefixes.contains(symbol) {
+            return;
+        }
+        let mut symbols: Vec<char> = user.prefixes.chars().chain(std::iter::once(symbol)).collect();
+        symbols.sort_by_key(|s| self.prefix.iter().position(|(_, p)| p == s).unwrap_or(usize::MAX));
+        user.prefixes = symbols.into_iter().collect();
+    }
+
+    pub fn remove_prefix(&self, user: &mut User, symbol: char) {
+        user.prefixes.retain(|s| s != symbol);
+    }
+
+    fn takes_arg(&self, mode: char, set: bool) -> bool {
+        self.symbol(mode).is_some()
+            || self.chanmodes[0].contains(mode)
+            || self.chanmodes[1].contains(mode)
+            || (set && self.chanmodes[2].contains(mode))
+    }
+
+    pub fn is_list(&self, mode: char) -> bool {
+        self.chanmodes[0].contains(mode)
+    }
+
+    // "+o-v+l" with its arguments, the params after the target of a MODE line
+    pub fn parse_changes(&self, params: &[String]) -> Vec<ModeChange> {
+        let mut changes = Vec::new();
+        let modes = match params.first() {
+            Some(m) => m,
+            None => return changes,
+        };
+        let mut args = params[1..].iter();
+        let mut set = true;
+        for mode in modes.chars() {
+            match mode {
+                '+' => set = true,
+                '-' => set = false,
+                _ => {
+                    let arg = if self.takes_arg(mode, set) { args.next().cloned() } else { None };
+                    changes.push(ModeChange { set, mode, arg });
+                }
+            }
+        }
+        changes
+    }
+}
+
+// "+klnt key 10" for the buffer title, empty without modes
+pub fn format_channel_modes(modes: &BTreeMap<char, Option<String>>) -> String {
+    if modes.is_empty() {
+        return String::new();
+    }
+    let letters: String = modes.keys().collect();
+    let args: Vec<&str> = modes.values().filter_map(|a| a.as_deref()).collect();
+    if args.is_empty() {
+        format!("+{}", letters)
+    } else {
+        format!("+{} {}", letters, args.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(line: &str) -> Vec<String> {
+        line.split(' ').map(|p| p.to_string()).collect()
+    }
+
+    fn change(set: bool, mode: char, arg: Option<&str>) -> ModeChange {
+        ModeChange { set, mode, arg: arg.map(|a| a.to_string()) }
+    }
+
+    #[test]
+    fn chanmodes_classes_take_their_arguments() {
+        let table = ModeTable::default();
+        // A (b) always, B (k) always, C (l) only when set, D (m) never, prefixes (o) always
+        let changes = table.parse_changes(&params("+bklmo *!*@spam key 10 nick"));
+        assert_eq!(changes, vec![
+            change(true, 'b', Some("*!*@spam")),
+            change(true, 'k', Some("key")),
+            change(true, 'l', Some("10")),
+            change(true, 'm', None),
+            change(true, 'o', Some("nick")),
+        ]);
+        let changes = table.parse_changes(&params("-bklmo *!*@spam key nick"));
+        assert_eq!(changes, vec![
+            change(false, 'b', Some("*!*@spam")),
+            change(false, 'k', Some("key")),
+            change(false, 'l', None),
+            change(false, 'm', None),
+            change(false, 'o', Some("nick")),
+        ]);
+    }
+
+    #[test]
+    fn server_chanmodes_and_missing_arguments() {
+        let mut table = ModeTable::default();
+        table.parse_chanmodes("eIbq,k,flj,CFLMPQScgimnprstz");
+        let changes = table.parse_changes(&params("+qj-f+v-b *!*@x 5:10 nick"));
+        assert_eq!(changes, vec![
+            change(true, 'q', Some("*!*@x")),
+            change(true, 'j', Some("5:10")),
+            change(false, 'f', None),
+            change(true, 'v', Some("nick")),
+            change(false, 'b', None),
+        ]);
+        assert!(table.parse_changes(&[]).is_empty());
+    }
+
+    #[test]
+    fn names_with_multi_prefix() {
+        let table = ModeTable::default();
+        let user = table.parse_user("@+nick");
+        assert_eq!((user.nick.as_str(), user.prefixes.as_str()), ("nick", "@+"));
+        // Kept in rank order whatever order the server sent
+        assert_eq!(table.parse_user("+@nick").prefixes, "@+");
+        assert_eq!(table.parse_user("nick!user@host").nick, "nick");
+        assert_eq!(table.parse_user("~&other!u@h").display(), "~other");
+        assert_eq!(table.parse_user("plain").prefixes, "");
+    }
+
+    #[test]
+    fn prefix_token() {
+        let mut table = ModeTable::default();
+        table.parse_prefix("(Yov)!@+");
+        assert_eq!(table.symbol('Y'), Some('!'));
+        let mut user = table.parse_user("+nick");
+        table.add_prefix(&mut user, '!');
+        assert_eq!(user.prefixes, "!+");
+        table.remove_prefix(&mut user, '!');
+        assert_eq!(table.rank(&user), 2);
+        assert_eq!(table.rank(&User::new("x")), 3);
+    }
+}
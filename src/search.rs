@@ -0,0 +1,176 @@
+// search.rs
+use regex::{Regex, RegexBuilder};
+use ratatui::widgets::ListState;
+use ratatui::text::Span;
+use chrono::{DateTime, Local};
+use crate::app::{App, LineKind};
+use crate::textstyle;
+use crate::cursor;
+
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    pub server: String,
+    pub channel: String,
+    // Position in chat_list when the search ran
+    pub index: usize,
+    pub time: DateTime<Local>,
+    pub nick: String,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Search {
+    pub regex: Option<Regex>,
+    pub results: Vec<SearchMatch>,
+    pub state: ListState,
+    // Ctrl-F mode, the prompt holds the query
+    pub incremental: bool,
+    // Match the active buffer is scrolled to, 0 is the newest
+    pub current: usize,
+}
+
+// Case insensitive unless the query has an uppercase letter
+pub fn compile(query: &str) -> Result<Regex, regex::Error> {
+    let smart_case = !query.chars().any(|c| c.is_uppercase());
+    RegexBuilder::new(query).case_insensitive(smart_case).build()
+}
+
+// Every chat line in every buffer matching the regex, formatting codes ignored
+pub fn find(app: &App, regex: &Regex) -> Vec<SearchMatch> {
+    let mut results = Vec::new();
+    for (server_id, server) in &app.server_list {
+        for (channel_id, channel) in server.channels.iter() {
+            for (index, line) in channel.chat_list.iter().enumerate() {
+                if !matches!(line.kind, LineKind::Message | LineKind::Backlog | LineKind::Action) {
+                    continue;
+                }
+                let text = textstyle::strip_formatting(&line.text);
+                if regex.is_match(&text) {
+                    results.push(SearchMatch {
+                        server: server_id.clone(),
+                        channel: channel_id.clone(),
+                        index,
+                        time: line.time,
+                        nick: line.nick.clone(),
+                        text,
+                    });
+                }
+            }
+        }
+    }
+    results
+}
+
+// /search <regex>, fills the results popup and jumps to the newest match in the active buffer
+pub fn run(app: &mut App, query: &str) -> Result<usize, regex::Error> {
+    let regex = compile(query)?;
+    app.search.results = find(app, &regex);
+    app.search.regex = Some(regex);
+    app.search.state.select(if app.search.results.is_empty() { None } else { Some(0) });
+    app.search.current = 0;
+    jump(app, 0);
+    Ok(app.search.results.len())
+}
+
+pub fn start_incremental(app: &mut App) {
+    app.search.incremental = true;
+    app.prompt.clear();
+    cursor::reset_cursor(app);
+    app.input_mode.clear();
+    app.input_mode.push(Span::from("Search"));
+}
+
+pub fn stop(app: &mut App) {
+    if app.search.incremental {
+        app.search.incremental = false;
+        app.prompt.clear();
+        cursor::reset_cursor(app);
+        app.input_mode.clear();
+        app.input_mode.push(Span::from("N"));
+    }
+    app.search = Search::default();
+}
+
+// Called on every prompt edit while in Ctrl-F mode
+pub fn update_incremental(app: &mut App) {
+    if !app.search.incremental {
+        return;
+    }
+    let query = textstyle::strip_formatting(&app.prompt);
+    if query.is_empty() {
+        app.search.regex = None;
+        app.search.results.clear();
+        return;
+    }
+    // Half typed patterns like "foo(" are searched as plain text
+    let regex = match compile(&query) {
+        Ok(r) => r,
+        Err(_) => match compile(&regex::escape(&query)) {
+            Ok(r) => r,
+            Err(_) => return,
+        },
+    };
+    app.search.results = find(app, &regex);
+    app.search.regex = Some(regex);
+    app.search.current = 0;
+    jump(app, 0);
+}
+
+// Moves step matches older in the active buffer, wrapping around to the newest
+pub fn jump(app: &mut App, step: usize) {
+    let mut lines: Vec<usize> = app.search.results.iter()
+        .filter(|m| m.server == app.active_server && m.channel == app.active_channel)
+        .map(|m| m.index)
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+    lines.sort();
+    app.search.current = (app.search.current + step) % lines.len();
+    let index = lines[lines.len() - 1 - app.search.current];
+    let (server_id, channel_id) = (app.active_server.clone(), app.active_channel.clone());
+    scroll_to(app, &server_id, &channel_id, index);
+}
+
+// Enter in the results popup, swaps to the buffer of the selected match
+pub fn open_selected(app: &mut App) {
+    let found = match app.search.state.selected().and_then(|i| app.search.results.get(i)) {
+        Some(m) => m.clone(),
+        None => return,
+    };
+    let server = match app.server_list.get_mut(&found.server) {
+        Some(s) => s,
+        None => return,
+    };
+    let channel = match server.channels.get_mut(&found.channel) {
+        Some(c) => c,
+        None => return,
+    };
+    channel.mark_read();
+    // Old lines get dropped from the front of long buffers, find the line again if it moved
+    let index = match channel.chat_list.get(found.index) {
+        Some(l) if l.time == found.time && l.nick == found.nick => found.index,
+        _ => match channel.chat_list.iter().position(|l| l.time == found.time && l.nick == found.nick) {
+            Some(i) => i,
+            None => return,
+        },
+    };
+    app.show_buffer(&found.server, &found.channel);
+    scroll_to(app, &found.server, &found.channel, index);
+}
+
+// Sets chat_pos so the line at index sits in the middle of the chat window
+pub fn scroll_to(app: &mut App, server_id: &str, channel_id: &str, index: usize) {
+    let (below, total): (usize, usize) = match app.server_list.get(server_id).and_then(|s| s.channels.get(channel_id)) {
+        Some(channel) => {
+            let heights: Vec<usize> = channel.chat_list.iter().map(|l| textstyle::line_height(app, server_id, channel_id, l)).collect();
+            (heights.iter().skip(index + 1).sum(), heights.iter().sum())
+        }
+        None => return,
+    };
+    let view = app.chat_rows(server_id, channel_id);
+    if let Some(channel) = app.server_list.get_mut(server_id).and_then(|s| s.channels.get_mut(channel_id)) {
+        // Same limit PageUp stops at
+        channel.chat_pos = below.saturating_sub(view / 2).min(total.saturating_sub(view));
+    }
+}
@@ -1,7 +1,17 @@
 // stream.rs
-use tokio::{net::TcpStream, io::{AsyncBufReadExt, AsyncWriteExt, BufReader}};
+use tokio::{net::TcpStream, io::{AsyncBufReadExt, AsyncWriteExt, AsyncRead, AsyncWrite, BufReader}};
 use tokio::sync::mpsc;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::VecDeque;
+use std::fmt;
+use crate::message::IrcMessage;
+use crate::cap::{CapNegotiation, CapAction, Sasl, DEFAULT_CAPS};
 
 
 pub struct ConnectionHandle {
@@ -10,6 +20,8 @@ pub struct ConnectionHandle {
 
 pub enum ConnCommand {
     SendLine(String),
+    // Skips the send queue, for PONG replies
+    SendPriority(String),
     Quit,
 }
 
@@ -19,73 +31,231 @@ pub type ServerId = String;
 pub enum NetEvent {
     Line(String),
     Error(String),
+    Status(String),
+    // Capabilities currently enabled on the connection
+    Caps(Vec<String>),
 }
 
+// Who we register as, oauth is only set for twitch
+#[derive(Clone, Default)]
+pub struct Identity {
+    pub nick: String,
+    pub real: String,
+    pub oauth: String,
+}
+
+impl Identity {
+    pub fn new(nick: &str, real: &str) -> Identity {
+        Identity { nick: nick.to_string(), real: real.to_string(), oauth: String::new() }
+    }
+}
+
+// Transport settings for a single server connection
+#[derive(Debug, Clone)]
+pub struct ConnOptions {
+    pub identity: Identity,
+    pub tls: bool,
+    pub tls_insecure: bool,
+    pub ca_file: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub sasl: Option<Sasl>,
+    pub caps: Vec<String>,
+    pub reconnect: Reconnect,
+    pub flood: FloodControl,
+}
+
+impl Default for ConnOptions {
+    fn default() -> Self {
+        Self {
+            identity: Identity::default(),
+            tls: false,
+            tls_insecure: false,
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            sasl: None,
+            caps: DEFAULT_CAPS.iter().map(|c| c.to_string()).collect(),
+            reconnect: Reconnect::default(),
+            flood: FloodControl::default(),
+        }
+    }
+}
+
+// Written out by hand so the oauth token never ends up in a log or error line
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("nick", &self.nick)
+            .field("real", &self.real)
+            .field("oauth", &if self.oauth.is_empty() { "" } else { "<redacted>" })
+            .finish()
+    }
+}
+
+// Token bucket limits for outgoing lines
+#[derive(Debug, Clone)]
+pub struct FloodControl {
+    pub burst: u32,
+    pub refill: Duration,
+}
+
+impl Default for FloodControl {
+    fn default() -> Self {
+        Self { burst: 5, refill: Duration::from_millis(2000) }
+    }
+}
+
+impl FloodControl {
+    // Twitch allows 20 messages per 30 seconds for regular users, a full bucket
+    // plus one line every 2s sends at most 3 + 15 = 18 in any 30s
+    pub fn twitch() -> Self {
+        Self { burst: 3, refill: Duration::from_millis(2000) }
+    }
+}
+
+struct SendQueue {
+    limits: FloodControl,
+    tokens: u32,
+    last_refill: Instant,
+    lines: VecDeque<String>,
+}
+
+impl SendQueue {
+    fn new(limits: FloodControl) -> Self {
+        Self { tokens: limits.burst, limits, last_refill: Instant::now(), lines: VecDeque::new() }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if self.limits.refill.is_zero() {
+            self.tokens = self.limits.burst;
+            return;
+        }
+        let earned = (now.duration_since(self.last_refill).as_millis() / self.limits.refill.as_millis()) as u32;
+        if earned > 0 {
+            self.tokens = (self.tokens + earned).min(self.limits.burst);
+            self.last_refill += self.limits.refill * earned;
+        }
+        if self.tokens == self.limits.burst {
+            self.last_refill = now;
+        }
+    }
+
+    fn pop_ready(&mut self) -> Option<String> {
+        self.refill();
+        if self.tokens == 0 {
+            return None;
+        }
+        let line = self.lines.pop_front()?;
+        self.tokens -= 1;
+        Some(line)
+    }
+
+    // Time until the next token, only meaningful while lines are waiting
+    fn wait(&self) -> Duration {
+        (self.last_refill + self.limits.refill).saturating_duration_since(Instant::now())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    pub enabled: bool,
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self { enabled: true, max_attempts: 10, base_delay: Duration::from_secs(2), max_delay: Duration::from_secs(300) }
+    }
+}
+
+impl Reconnect {
+    // Exponential backoff capped at max_delay, plus up to 25% jitter so servers dont see everyone at once
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(self.max_delay);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let jitter = backoff.mul_f64((nanos % 1000) as f64 / 4000.0);
+        backoff + jitter
+    }
+}
+
+// Plain tcp and tls streams are both driven through this, so the read/write loop doesnt care which one it got
+trait IrcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IrcStream for T {}
+
 #[derive(Default)]
 pub struct StreamManager {
     conns: HashMap<ServerId, ConnectionHandle>,
 }
 
 impl StreamManager {
-    pub async fn connect(&mut self, server_id: ServerId, addr: String, net_tx: mpsc::UnboundedSender<(ServerId, NetEvent)>, nick: String, real: String, oauth: String) -> bool{
+    // Failures show up later as NetEvent::Error from the connection task
+    pub fn connect(&mut self, server_id: ServerId, addr: String, net_tx: mpsc::UnboundedSender<(ServerId, NetEvent)>, opts: ConnOptions) {
         let (tx, mut rx) = mpsc::unbounded_channel();
         let net_tx2 = net_tx.clone();
         let sid = server_id.clone();
         tokio::spawn(async move {
-            let stream = match TcpStream::connect(&addr).await {
-                Ok(s) => s,
-                Err(e) => {
-                    let _ = net_tx2.send((sid.clone(), NetEvent::Error(format!("Failed to connect: {e}"))));
-                    return;
-                }
-            };
-            let (r, mut w) = stream.into_split();
-            let mut reader = BufReader::new(r).lines();
-
-            let w_oauth = "PASS oauth:".to_owned() + &oauth + "\r\n";
-            let cap_user = "CAP REQ :twitch.tv/membership".to_owned() + "\r\n";
-            let w_nick = "NICK ".to_owned() + &nick + "\r\n";
-            let w_real = "USER guest 0 * :".to_owned() + &real + "\r\n";
-
-            if oauth.is_empty() {
-                let _ = w.write_all(w_nick.as_bytes()).await;
-                let _ = w.write_all(w_real.as_bytes()).await;
-            } else {
-                let _ = w.write_all(w_oauth.as_bytes()).await;
-                let _ = w.write_all(w_nick.as_bytes()).await;
-                let _ = w.write_all(cap_user.as_bytes()).await;
-            }
-
+            let mut attempt: u32 = 0;
+            let mut was_connected = false;
             loop {
-                tokio::select! {
-                    Some(cmd) = rx.recv() => match cmd {
-                        ConnCommand::SendLine(s) => {
-                            let _ = w.write_all(s.as_bytes()).await;
-                            let _ = w.write_all(b"\r\n").await; 
+                let outcome = run_session(&sid, &addr, &net_tx2, &mut rx, &opts).await;
+                let reason = match outcome {
+                    Session::Quit => break,
+                    Session::Aborted(reason) => {
+                        let _ = net_tx2.send((sid.clone(), NetEvent::Error(reason)));
+                        break;
+                    }
+                    Session::Failed(reason) => {
+                        // A server that never answered is most likely a typo, dont keep retrying it
+                        if !was_connected {
+                            let _ = net_tx2.send((sid.clone(), NetEvent::Error(format!("Failed to connect: {reason}"))));
+                            break;
                         }
-                        ConnCommand::Quit => break,
-                    },
-                    result = reader.next_line() => {
-                        match result {
-                            Ok(Some(line)) => {
-                                let _ = net_tx2.send((sid.clone(), NetEvent::Line(line)));
-                            }
-                            Ok(None) => {
-                                let _ = net_tx2.send((sid.clone(), NetEvent::Error("Disconnected".to_string())));
-                                break;
-                            }
-                            Err(e) => {
-                                let _ = net_tx2.send((sid.clone(), NetEvent::Error(format!("Read error: {e}"))));
+                        format!("Failed to connect: {reason}")
+                    }
+                    Session::Lost { reason, registered } => {
+                        was_connected = true;
+                        if registered {
+                            attempt = 0;
+                        }
+                        reason
+                    }
+                };
+
+                attempt += 1;
+                if !opts.reconnect.enabled || attempt > opts.reconnect.max_attempts {
+                    let _ = net_tx2.send((sid.clone(), NetEvent::Error(reason)));
+                    break;
+                }
+                let delay = opts.reconnect.delay(attempt);
+                let _ = net_tx2.send((sid.clone(), NetEvent::Status(format!("{reason}, reconnecting in {:.1}s (attempt {}/{})", delay.as_secs_f32(), attempt, opts.reconnect.max_attempts))));
+
+                // Keep listening for /disconnect while waiting, lines sent meanwhile are dropped
+                let sleep = tokio::time::sleep(delay);
+                tokio::pin!(sleep);
+                let mut quit = false;
+                loop {
+                    tokio::select! {
+                        _ = &mut sleep => break,
+                        cmd = rx.recv() => match cmd {
+                            Some(ConnCommand::SendLine(_)) | Some(ConnCommand::SendPriority(_)) => {}
+                            Some(ConnCommand::Quit) | None => {
+                                quit = true;
                                 break;
                             }
-                        }
+                        },
                     }
                 }
+                if quit {
+                    break;
+                }
             }
         });
 
         self.conns.insert(server_id, ConnectionHandle { tx });
-        true
     }
 
     pub fn send_line(&self, server_id: String, line:String) {
@@ -93,6 +263,11 @@ impl StreamManager {
             let _ = conn.tx.send(ConnCommand::SendLine(line));
         }
     }
+    pub fn send_priority(&self, server_id: String, line:String) {
+        if let Some(conn) = self.conns.get(&server_id) {
+            let _ = conn.tx.send(ConnCommand::SendPriority(line));
+        }
+    }
     pub fn disconnect(&mut self, server_id: &str) {
         if let Some(conn) = self.conns.remove(server_id) {
             // Send the Quit command to the task
@@ -108,3 +283,332 @@ impl StreamManager {
 
 }
 
+enum Session {
+    // /disconnect or /quit
+    Quit,
+    // Registration refused, retrying wont help
+    Aborted(String),
+    // Could not open the connection at all
+    Failed(String),
+    Lost { reason: String, registered: bool },
+}
+
+async fn run_session(sid: &ServerId, addr: &str, net_tx: &mpsc::UnboundedSender<(ServerId, NetEvent)>, rx: &mut mpsc::UnboundedReceiver<ConnCommand>, opts: &ConnOptions) -> Session {
+    let stream = match open_stream(addr, opts).await {
+        Ok(s) => s,
+        Err(e) => return Session::Failed(e),
+    };
+    let (r, mut w) = tokio::io::split(stream);
+    let mut reader = BufReader::new(r).lines();
+    // Nothing is negotiated yet on a fresh connection
+    let _ = net_tx.send((sid.clone(), NetEvent::Caps(Vec::new())));
+
+    let id = &opts.identity;
+    let w_oauth = IrcMessage::new("PASS", &[&("oauth:".to_owned() + &id.oauth)]).to_string();
+    let w_nick = IrcMessage::new("NICK", &[&id.nick]).to_string();
+    let w_real = IrcMessage::new("USER", &["guest", "0", "*", &id.real]).to_string();
+
+    let mut caps = CapNegotiation::new(opts.caps.clone(), opts.sasl.clone());
+    let mut registered = false;
+
+    let opening = if id.oauth.is_empty() {
+        [caps.start(), vec![w_nick, w_real]].concat()
+    } else {
+        [vec![w_oauth, w_nick], caps.start_twitch()].concat()
+    };
+    for l in opening {
+        let _ = w.write_all(l.as_bytes()).await;
+        let _ = w.write_all(b"\r\n").await;
+    }
+
+    let mut queue = SendQueue::new(opts.flood.clone());
+
+    let session = loop {
+        tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(ConnCommand::SendLine(s)) => {
+                    queue.lines.push_back(s);
+                    while let Some(l) = queue.pop_ready() {
+                        let _ = w.write_all(l.as_bytes()).await;
+                        let _ = w.write_all(b"\r\n").await;
+                    }
+                    let queued = queue.lines.len();
+                    if queued == 1 || (queued > 0 && queued.is_multiple_of(5)) {
+                        let _ = net_tx.send((sid.clone(), NetEvent::Status(format!("Flood control: {queued} lines queued"))));
+                    }
+                }
+                Some(ConnCommand::SendPriority(s)) => {
+                    let _ = w.write_all(s.as_bytes()).await;
+                    let _ = w.write_all(b"\r\n").await;
+                }
+                Some(ConnCommand::Quit) | None => break Session::Quit,
+            },
+            _ = tokio::time::sleep(queue.wait()), if !queue.lines.is_empty() => {
+                while let Some(l) = queue.pop_ready() {
+                    let _ = w.write_all(l.as_bytes()).await;
+                    let _ = w.write_all(b"\r\n").await;
+                }
+                if queue.lines.is_empty() {
+                    let _ = net_tx.send((sid.clone(), NetEvent::Status("Flood control: send queue empty".to_string())));
+                }
+            }
+            result = reader.next_line() => {
+                match result {
+                    Ok(Some(line)) => {
+                        let msg = IrcMessage::parse(&line);
+                        if !registered && msg.as_ref().is_some_and(|m| m.command == "001") {
+                            registered = true;
+                        }
+                        let _ = net_tx.send((sid.clone(), NetEvent::Line(line)));
+                        let before = caps.enabled.len();
+                        let action = msg.map(|m| caps.on_message(&m)).unwrap_or(CapAction::None);
+                        match action {
+                            CapAction::Send(lines) => {
+                                for l in lines {
+                                    let _ = w.write_all(l.as_bytes()).await;
+                                    let _ = w.write_all(b"\r\n").await;
+                                }
+                            }
+                            CapAction::Abort(reason) => {
+                                let _ = w.write_all(b"QUIT :Registration aborted\r\n").await;
+                                break Session::Aborted(reason);
+                            }
+                            CapAction::None => {}
+                        }
+                        if caps.enabled.len() != before {
+                            let _ = net_tx.send((sid.clone(), NetEvent::Caps(caps.enabled.clone())));
+                        }
+                    }
+                    Ok(None) => break Session::Lost { reason: "Disconnected".to_string(), registered },
+                    Err(e) => break Session::Lost { reason: format!("Read error: {e}"), registered },
+                }
+            }
+        }
+    };
+    // Flush the QUIT and close_notify before dropping a tls session
+    let _ = w.shutdown().await;
+    session
+}
+
+async fn open_stream(addr: &str, opts: &ConnOptions) -> Result<Box<dyn IrcStream>, String> {
+    let tcp = TcpStream::connect(addr).await.map_err(|e| e.to_string())?;
+    if !opts.tls {
+        return Ok(Box::new(tcp));
+    }
+
+    let host = addr.rsplit_once(':').map(|(h, _)| h).unwrap_or(addr);
+    let server_name = ServerName::try_from(host.to_string()).map_err(|e| format!("Invalid server name {host}: {e}"))?;
+    let connector = TlsConnector::from(Arc::new(tls_config(opts)?));
+    let tls = connector.connect(server_name, tcp).await.map_err(|e| format!("TLS handshake failed: {e}"))?;
+    Ok(Box::new(tls))
+}
+
+fn tls_config(opts: &ConnOptions) -> Result<ClientConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+
+    let builder = if opts.tls_insecure {
+        //Self signed certs, skip verification entirely
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerify(provider)))
+    } else {
+        builder.with_root_certificates(root_store(opts)?)
+    };
+
+    // Client certificate, used for SASL EXTERNAL
+    match (&opts.client_cert, &opts.client_key) {
+        (Some(cert_path), key_path) => {
+            let pem = std::fs::read(cert_path).map_err(|e| format!("Could not read client cert {cert_path}: {e}"))?;
+            let certs = rustls_pemfile::certs(&mut pem.as_slice()).collect::<Result<Vec<_>, _>>().map_err(|e| format!("Invalid client cert {cert_path}: {e}"))?;
+            // The key can live in the same pem file as the cert
+            let key_path = key_path.as_ref().unwrap_or(cert_path);
+            let pem = std::fs::read(key_path).map_err(|e| format!("Could not read client key {key_path}: {e}"))?;
+            let key = rustls_pemfile::private_key(&mut pem.as_slice())
+                .map_err(|e| format!("Invalid client key {key_path}: {e}"))?
+                .ok_or(format!("No private key found in {key_path}"))?;
+            builder.with_client_auth_cert(certs, key).map_err(|e| e.to_string())
+        }
+        (None, _) => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn root_store(opts: &ConnOptions) -> Result<RootCertStore, String> {
+
+    let mut roots = RootCertStore::empty();
+    match &opts.ca_file {
+        Some(path) => {
+            let pem = std::fs::read(path).map_err(|e| format!("Could not read ca file {path}: {e}"))?;
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                let cert = cert.map_err(|e| format!("Invalid certificate in {path}: {e}"))?;
+                roots.add(cert).map_err(|e| format!("Invalid certificate in {path}: {e}"))?;
+            }
+        }
+        None => {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+    }
+    Ok(roots)
+}
+
+#[derive(Debug)]
+struct NoVerify(Arc<rustls::crypto::CryptoProvider>);
+
+impl ServerCertVerifier for NoVerify {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tokio_rustls::rustls::server::WebPkiClientVerifier;
+
+    // A throwaway CA with a server and a client cert signed by it
+    struct TestPki {
+        ca_pem: String,
+        ca_der: CertificateDer<'static>,
+        server_der: CertificateDer<'static>,
+        server_key: Vec<u8>,
+        client_pem: String,
+    }
+
+    fn pki() -> TestPki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()]).unwrap().signed_by(&server_key, &ca, &ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client = CertificateParams::new(vec!["client".to_string()]).unwrap().signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        TestPki {
+            ca_pem: ca.pem(),
+            ca_der: ca.der().clone(),
+            server_der: server.der().clone(),
+            server_key: server_key.serialize_der(),
+            // Cert and key in one file, like client_cert without a client_key
+            client_pem: client.pem() + &client_key.serialize_pem(),
+        }
+    }
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rustychat-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // Accepts one tls connection and greets it, Ok(true) when the client showed a certificate
+    async fn serve_once(pki: &TestPki, require_client_cert: bool) -> (String, tokio::task::JoinHandle<Result<bool, String>>) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions().unwrap();
+        let builder = if require_client_cert {
+            let mut roots = RootCertStore::empty();
+            roots.add(pki.ca_der.clone()).unwrap();
+            builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap())
+        } else {
+            builder.with_no_client_auth()
+        };
+        let key = PrivateKeyDer::try_from(pki.server_key.clone()).unwrap();
+        let config = builder.with_single_cert(vec![pki.server_der.clone()], key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.map_err(|e| e.to_string())?;
+            let mut tls = acceptor.accept(tcp).await.map_err(|e| e.to_string())?;
+            let client_cert = tls.get_ref().1.peer_certificates().is_some();
+            tls.write_all(b"PING :hello\r\n").await.map_err(|e| e.to_string())?;
+            let _ = tls.shutdown().await;
+            Ok(client_cert)
+        });
+        (addr, server)
+    }
+
+    async fn first_line(stream: Box<dyn IrcStream>) -> String {
+        let mut lines = BufReader::new(stream).lines();
+        lines.next_line().await.unwrap().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn tls_with_custom_ca() {
+        let pki = pki();
+        let (addr, server) = serve_once(&pki, false).await;
+        let opts = ConnOptions { tls: true, ca_file: Some(temp_file("ca.pem", &pki.ca_pem)), ..Default::default() };
+        let stream = open_stream(&addr, &opts).await;
+        let _ = std::fs::remove_file(opts.ca_file.unwrap());
+        assert_eq!(first_line(stream.unwrap()).await, "PING :hello");
+        assert_eq!(server.await.unwrap(), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn tls_rejects_unknown_ca() {
+        let pki = pki();
+        let (addr, _server) = serve_once(&pki, false).await;
+        let opts = ConnOptions { tls: true, ..Default::default() };
+        let err = open_stream(&addr, &opts).await.err().unwrap();
+        assert!(err.starts_with("TLS handshake failed"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn tls_insecure_skips_verification() {
+        let pki = pki();
+        let (addr, server) = serve_once(&pki, false).await;
+        let opts = ConnOptions { tls: true, tls_insecure: true, ..Default::default() };
+        let stream = open_stream(&addr, &opts).await.unwrap();
+        assert_eq!(first_line(stream).await, "PING :hello");
+        assert_eq!(server.await.unwrap(), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn tls_client_cert() {
+        let pki = pki();
+        let (addr, server) = serve_once(&pki, true).await;
+        let opts = ConnOptions {
+            tls: true,
+            ca_file: Some(temp_file("client-ca.pem", &pki.ca_pem)),
+            client_cert: Some(temp_file("client.pem", &pki.client_pem)),
+            ..Default::default()
+        };
+        let stream = open_stream(&addr, &opts).await;
+        let _ = std::fs::remove_file(opts.ca_file.unwrap());
+        let _ = std::fs::remove_file(opts.client_cert.unwrap());
+        assert_eq!(first_line(stream.unwrap()).await, "PING :hello");
+        assert_eq!(server.await.unwrap(), Ok(true));
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        let opts = ConnOptions {
+            identity: Identity { oauth: "token123".to_string(), ..Identity::new("nick", "real") },
+            sasl: Some(Sasl::Plain { account: "acct".to_string(), password: "hunter2".to_string() }),
+            ..Default::default()
+        };
+        let text = format!("{opts:?}");
+        assert!(text.contains("acct") && text.contains("nick"));
+        assert!(!text.contains("hunter2") && !text.contains("token123"));
+    }
+}
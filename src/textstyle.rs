@@ -4,12 +4,29 @@ use ratatui::style::Style;
 use ratatui::style::Color;
 use ratatui::style::Modifier;
 use crate::app::App;
+use crate::app::LineKind;
+use crate::app::ChatLine;
+use regex::Regex;
 use ratatui::text::Line;
 use textwrap::{wrap, Options};
 use std::borrow::Cow;
 use crate::cursor;
 
 
+// Readable on the dark default background, a nick keeps its color everywhere
+const NICK_COLORS: [(u8, u8, u8); 12] = [
+    (230, 120, 120), (230, 170, 100), (220, 210, 110), (160, 220, 110),
+    (100, 210, 150), (100, 210, 210), (110, 170, 240), (150, 140, 240),
+    (200, 130, 230), (230, 130, 190), (180, 180, 180), (200, 170, 140),
+];
+
+pub fn nick_color(nick: &str) -> Color {
+    // FNV-1a over the lowercase nick
+    let hash = nick.to_ascii_lowercase().bytes().fold(0x811c9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193));
+    let (r, g, b) = NICK_COLORS[hash as usize % NICK_COLORS.len()];
+    Color::Rgb(r, g, b)
+}
+
 #[derive(Clone)]
 struct StyleState {
     bold: bool,
@@ -63,7 +80,7 @@ fn line_wrap(width: usize, data: &str) -> Vec<Cow<str>> {
     return data_wrap;
 }
 
-fn text_style<'a, F>(line: &Cow<str>, mut spans: Vec<Span<'a>>, mut current_style: Style, app: &App, mut on_color_change: F) -> Vec<Span<'a>> where F: FnMut(&str, &str) {
+pub fn text_style<'a, F>(line: &Cow<str>, mut spans: Vec<Span<'a>>, mut current_style: Style, app: &App, mut on_color_change: F) -> Vec<Span<'a>> where F: FnMut(&str, &str) {
     let mut text = String::new();
     let mut chars = line.chars().peekable();
     let mut styles = StyleState::default();
@@ -167,16 +184,103 @@ fn text_style<'a, F>(line: &Cow<str>, mut spans: Vec<Span<'a>>, mut current_styl
     spans
 }
 
+// Plain text without mIRC formatting codes, color digits included
+pub fn strip_formatting(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{3}' => {
+                for _ in 0..2 {
+                    if chars.peek().is_some_and(|c| c.is_ascii_digit()) { chars.next(); }
+                }
+                let mut ahead = chars.clone();
+                if ahead.next() == Some(',') && ahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    chars.next();
+                    for _ in 0..2 {
+                        if chars.peek().is_some_and(|c| c.is_ascii_digit()) { chars.next(); }
+                    }
+                }
+            }
+            '\u{2}' | '\u{1D}' | '\u{1F}' | '\u{1E}' | '\u{11}' | '\u{16}' | '\u{F}' | '\u{1}' => {}
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+// Width left for the message text next to the "time nick: " prefix, in the pane showing the buffer
+pub fn wrap_width(app: &App, server_id: &str, channel_id: &str, prefix_len: usize) -> usize {
+    (app.pane_width(server_id, channel_id) as usize).saturating_sub(4 + prefix_len).max(1)
+}
+
+// Rows a chat line takes up in the chat window
+pub fn line_height(app: &App, server_id: &str, channel_id: &str, chat_line: &ChatLine) -> usize {
+    match chat_line.kind {
+        LineKind::DayChange | LineKind::BacklogEnd => 1,
+        _ => {
+            let stamp = chat_line.time.format(&app.timestamp_format).to_string();
+            let stamp_len = if stamp.is_empty() { 0 } else { stamp.chars().count() + 1 };
+            line_wrap(wrap_width(app, server_id, channel_id, stamp_len + 12), &line_text(chat_line)).len().max(1)
+        }
+    }
+}
+
+// Actions carry the nick in the text, "* nick waves"
+fn line_text(chat_line: &ChatLine) -> String {
+    match chat_line.kind {
+        LineKind::Action => format!("{} {}", chat_line.nick, chat_line.text),
+        _ => chat_line.text.clone(),
+    }
+}
+
+// Splits spans around regex matches and reverses the matched parts
+fn highlight_matches<'a>(spans: Vec<Span<'a>>, regex: &Regex) -> Vec<Span<'a>> {
+    let mut out = Vec::new();
+    for span in spans {
+        let content = span.content.to_string();
+        let mut last = 0;
+        for m in regex.find_iter(&content).filter(|m| !m.is_empty()) {
+            if m.start() > last {
+                out.push(Span::styled(content[last..m.start()].to_string(), span.style));
+            }
+            out.push(Span::styled(m.as_str().to_string(), span.style.add_modifier(Modifier::REVERSED)));
+            last = m.end();
+        }
+        if last == 0 {
+            out.push(span);
+        } else if last < content.len() {
+            out.push(Span::styled(content[last..].to_string(), span.style));
+        }
+    }
+    out
+}
+
 pub fn chat_style(app: &App, server_id: String, channel_id: String) -> Vec<Line> {
+    // Search matches are highlighted in the active buffer only
+    let search = if server_id == app.active_server && channel_id == app.active_channel { app.search.regex.as_ref() } else { None };
 
     let mut chat_lines: Vec<Line> = Vec::new();
 
     if let Some(server) = app.server_list.get(&server_id) {
         if let Some(channel) = server.channels.get(&channel_id) {
 
-            for (sender, line) in channel.chat_list.clone() {
-                //let (sender, line) = lines;
-
+            for chat_line in &channel.chat_list {
+                if chat_line.kind == LineKind::DayChange {
+                    let (fr, fg, fb) = app.style_fg;
+                    chat_lines.push(Line::from(Span::styled(format!("─── {} ───", chat_line.text), Style::default().fg(Color::Rgb(fr, fg, fb)))).centered());
+                    continue;
+                }
+                if chat_line.kind == LineKind::BacklogEnd {
+                    let (fr, fg, fb) = app.style_fg;
+                    chat_lines.push(Line::from(Span::styled(chat_line.text.clone(), Style::default().fg(Color::Rgb(fr, fg, fb)).add_modifier(Modifier::DIM))).centered());
+                    continue;
+                }
+                // Lines read back from the log are dimmed
+                let dim = chat_line.kind == LineKind::Backlog;
+                let action = chat_line.kind == LineKind::Action;
+                let sender = if action { "*" } else { chat_line.nick.as_str() };
+                let line = &line_text(chat_line);
 
                 let nick_width = 10;
 
@@ -187,38 +291,49 @@ pub fn chat_style(app: &App, server_id: String, channel_id: String) -> Vec<Line>
                 } else {
                     format!("{:<width$}", sender, width = nick_width)
                 };
-                let prefix = format!("{}: ", trimmed_nick);
+                let stamp = chat_line.time.format(&app.timestamp_format).to_string();
+                let stamp = if stamp.is_empty() { stamp } else { stamp + " " };
+                let prefix = if action { format!("{}  ", trimmed_nick) } else { format!("{}: ", trimmed_nick) };
+                let prefix_len = stamp.chars().count() + prefix.len();
 
-                let wrap_width;
                 //chat window horizontal "linewrap"
-                let (on, _, _, _, _) = app.split;
-                if on == true {
-                    wrap_width = (app.w as usize / 2) - 6 - prefix.len();
-                } else {
-                    wrap_width = app.w as usize-4 - prefix.len();
-                }
+                let wrap_width = wrap_width(app, &server_id, &channel_id, prefix_len);
 
                 let (tr, tg, tb) = app.style_txt;
-                let current_style = Style::new().fg(Color::Rgb(tr, tg, tb));
+                let mut current_style = Style::new().fg(Color::Rgb(tr, tg, tb));
+                if dim {
+                    current_style = current_style.add_modifier(Modifier::DIM);
+                }
+                if action {
+                    let (nr, ng, nb) = app.style_notif;
+                    current_style = Style::new().fg(Color::Rgb(nr, ng, nb)).add_modifier(Modifier::ITALIC);
+                }
 
-                let data_wrap = line_wrap(wrap_width, &line);
+                let data_wrap = line_wrap(wrap_width, line);
 
                 for (i, line) in data_wrap.iter().enumerate() {
 
                     let mut spans = Vec::new();
                     // Add the prefix span to the start of this line EG Nick with spacing
                     if i == 0 {
+                        let (fr, fg, fb) = app.style_fg;
+                        spans.push(Span::styled(stamp.clone(), Style::default().fg(Color::Rgb(fr, fg, fb))));
                         let (hr, hg, hb) = app.style_highlight;
+                        let nick_style = if dim { Modifier::DIM } else { Modifier::BOLD };
                         spans.push(Span::styled(
                             prefix.clone(),
-                            Style::default().fg(Color::Rgb(hr, hg, hb)).add_modifier(Modifier::BOLD),
+                            Style::default().fg(Color::Rgb(hr, hg, hb)).add_modifier(nick_style),
                         ));
                     } else {
                         // Add blank prefix of the same width for alignment
-                        spans.push(Span::raw(" ".repeat(prefix.len())));
+                        spans.push(Span::raw(" ".repeat(prefix_len)));
                     }
 
-                    let spans = text_style(line, spans, current_style, app, |_,_| {});
+                    let mut text_spans = text_style(line, Vec::new(), current_style, app, |_,_| {});
+                    if let Some(regex) = search {
+                        text_spans = highlight_matches(text_spans, regex);
+                    }
+                    spans.extend(text_spans);
                     chat_lines.push(Line::from(spans));
                 }
             }
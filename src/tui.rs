@@ -1,18 +1,24 @@
 // tui.rs
 use crate::app::App;
 use crate::app::Popup;
+use crate::app::Activity;
+use crate::layout::Pane;
 use crate::textstyle;
+use crate::command;
+use crate::userlist::{self, Confirm};
+use crate::chanlist::ListSort;
 use ratatui::{Frame, widgets::{Block, Borders, Paragraph, Tabs, RenderDirection, Sparkline}};
-use ratatui::widgets::{List, Clear, Wrap, BorderType, ListItem};
+use ratatui::widgets::{List, Clear, Wrap, BorderType, ListItem, ListState};
 use ratatui::style::{Color, Style, Modifier};
 use ratatui::text::{Line, Span};
 use ratatui::layout::Flex;
-use ratatui::prelude::{Rect, Layout, Position, Alignment}; 
+use ratatui::prelude::{Rect, Layout, Position}; 
 use ratatui::prelude::Constraint::{Percentage, Fill, Min, Length};
 use ratatui::symbols::bar::Set;
 use strum_macros::{FromRepr, EnumIter, Display};
 use strum::IntoEnumIterator;
 use unicode_width::UnicodeWidthStr;
+use std::borrow::Cow;
 
 #[derive(Default, Clone, Copy, Display, FromRepr, EnumIter)]
 enum SelectedTab {
@@ -62,21 +68,31 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
     let block = Block::default().style(Style::default().bg(Color::Rgb(color_map.bg. 0,color_map.bg.1, color_map.bg.2))).borders(Borders::NONE);
     frame.render_widget(block, area);
 
-    let threshold = app.w as usize - 12 - UnicodeWidthStr::width(app.active_channel.as_str()) - UnicodeWidthStr::width(app.active_nick.as_str()) - UnicodeWidthStr::width(app.active_server.as_str());
+    // Away marker behind the nick in the status bar
+    let away = if app.server_list.get(&app.active_server).is_some_and(|s| s.away) { " (away)" } else { "" };
+    let threshold = (app.w as usize)
+        .saturating_sub(12)
+        .saturating_sub(away.len())
+        .saturating_sub(UnicodeWidthStr::width(app.active_channel.as_str()))
+        .saturating_sub(UnicodeWidthStr::width(app.active_nick.as_str()))
+        .saturating_sub(UnicodeWidthStr::width(app.active_server.as_str()));
     let binding = app.prompt.clone();
     let (visible_prompt, cursor_offset_x) = textstyle::visible_prompt_and_cursor_offset(&binding, threshold, app);
     let input_title: Vec<Span> = itertools::Itertools::intersperse(app.input_mode.clone().into_iter(), Span::from("|"),).collect();
     let input = Paragraph::new(Line::from(visible_prompt)).block(Block::bordered().title(Line::from(input_title.clone()).right_aligned()).border_type(BorderType::default()).border_style(Style::new().fg(Color::Rgb(color_map.fg. 0,color_map.fg.1, color_map.fg.2))).borders(Borders::TOP | Borders::RIGHT | Borders::BOTTOM));
 
     let vertical_layout = Layout::vertical([Length(1), Min(0), Length(3)]);
-    let [info_bar, stream_area, input_horizontal_area] = vertical_layout.areas(frame.area()); 
+    let [info_bar, body_area, input_horizontal_area] = vertical_layout.areas(frame.area()); 
+    let sidebar_width = if app.sidebar { app.sidebar_width } else { 0 };
+    let nicklist_width = if app.nicklist_shown() { app.nicklist_width } else { 0 };
+    let [sidebar_area, chat_area, nicklist_area] = Layout::horizontal([Length(sidebar_width), Min(0), Length(nicklist_width)]).areas(body_area);
 
     let horizontal_info_layout = Layout::horizontal([Fill(1), Length(38), Fill(1)]);
     let [ spark_area, tab_area, spark2_area] = horizontal_info_layout.areas(info_bar);
-    let horizontal_input_layout = Layout::horizontal([Length(10+app.active_channel.len().try_into().unwrap_or(0)+app.active_nick.len().try_into().unwrap_or(0)+app.active_server.len().try_into().unwrap_or(0)), Fill(1)]);
+    let horizontal_input_layout = Layout::horizontal([Length(10+app.active_channel.len().try_into().unwrap_or(0)+app.active_nick.len().try_into().unwrap_or(0)+app.active_server.len().try_into().unwrap_or(0)+away.len() as u16), Fill(1)]);
     let [nick_area, input_area] = horizontal_input_layout.areas(input_horizontal_area);
 
-    let nick_layout = Paragraph::new(Span::from(format!("{} 🮥 {} 🮥 {} 🮥", app.active_server, app.active_channel,  app.active_nick.trim())).style(Style::new().fg(Color::Rgb(color_map.txt.0, color_map.txt.1, color_map.txt.2)))).block(Block::bordered().border_type(BorderType::default()).border_style(Style::new().fg(Color::Rgb(color_map.fg. 0,color_map.fg.1, color_map.fg.2))).borders(Borders::TOP | Borders::LEFT | Borders::BOTTOM));
+    let nick_layout = Paragraph::new(Span::from(format!("{} 🮥 {} 🮥 {}{} 🮥", app.active_server, app.active_channel,  app.active_nick.trim(), away)).style(Style::new().fg(Color::Rgb(color_map.txt.0, color_map.txt.1, color_map.txt.2)))).block(Block::bordered().border_type(BorderType::default()).border_style(Style::new().fg(Color::Rgb(color_map.fg. 0,color_map.fg.1, color_map.fg.2))).borders(Borders::TOP | Borders::LEFT | Borders::BOTTOM));
     let sparkline = Sparkline::default().bar_set(CUSTOM_SET_SPARK).data(&app.spark_data.clone()).style(ratatui::style::Style::default().fg(Color::Rgb(color_map.fg. 0,color_map.fg.1, color_map.fg.2)));
     let sparkline_rev = Sparkline::default().direction(RenderDirection::RightToLeft).bar_set(CUSTOM_SET_SPARK).data(&app.spark_data.clone()).style(ratatui::style::Style::default().fg(Color::Rgb(color_map.fg. 0,color_map.fg.1, color_map.fg.2)));
     let tab_titles = SelectedTab::iter().map(|tab| tab.title(color_map.txt.0, color_map.txt.1, color_map.txt.2));
@@ -88,11 +104,15 @@ pub fn draw(frame: &mut Frame, app: &mut App) {
     frame.render_widget(tabs, tab_area);
     frame.render_widget(nick_layout, nick_area);
 
-    if app.split.0 {
-        render_split_main(frame, app, &color_map, stream_area);
-    } else {
-        render_main(frame, app, &color_map, stream_area);
+    if app.sidebar {
+        render_sidebar(frame, app, &color_map, sidebar_area);
+    }
+    if app.nicklist_shown() {
+        render_nicklist(frame, app, &color_map, nicklist_area);
     }
+    app.layout.sync(&app.active_server, &app.active_channel);
+    app.layout.layout(chat_area);
+    render_panes(frame, app, &color_map);
 
     draw_popup(frame, app, &color_map);
 
@@ -107,6 +127,8 @@ fn draw_popup(frame: &mut Frame, app: &mut App, colors: &Colors) {
         Popup::Help    => render_help_pop(frame, colors),
         Popup::User    => render_user_pop(frame, app, colors),
         Popup::Channel => render_chan_pop(frame, app, colors),
+        Popup::Search  => render_search_pop(frame, app, colors),
+        Popup::Whois   => render_whois_pop(frame, app, colors),
     }
 }
 
@@ -122,22 +144,48 @@ fn render_color_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
 }
 
 fn render_list_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
-    let mut list_lines: Vec<Line> = Vec::new();
-    let list_length = app.list_response.len();
-    for line in app.list_response.clone() {
-        list_lines.push(Line::from(line));
+    let list_popup_area = pop_area(frame.area(), 80, 70);
+    let [filter_area, list_area] = Layout::vertical([Length(3), Min(0)]).areas(list_popup_area);
+
+    // Only the rows on screen are built, a full LIST runs into tens of thousands
+    app.chanlist.refresh();
+    let rows = list_area.height.saturating_sub(2) as usize;
+    let list = &mut app.chanlist;
+    if list.selected < list.offset {
+        list.offset = list.selected;
+    } else if rows > 0 && list.selected >= list.offset + rows {
+        list.offset = list.selected + 1 - rows;
     }
 
-    let list_block = if list_length > ((app.h as usize * 70) / 100) - 1 {
-        let slice = &list_lines[app.list_pos..((app.h as usize * 70) / 100) - 1 + app.list_pos];
-        Paragraph::new(slice.to_vec()).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(list_length.to_string()).right_aligned()))
+    let list = &app.chanlist;
+    let txt = Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2));
+    let name_width = list.entries.iter().map(|e| e.channel.width()).max().unwrap_or(0).min(30);
+    let list_lines: Vec<ListItem> = list.shown.iter().skip(list.offset).take(rows).map(|i| {
+        let entry = &list.entries[*i];
+        let mut spans = vec![
+            Span::styled(format!("{:<width$} ", entry.channel, width = name_width), Style::new().fg(Color::Rgb(c.highlight.0, c.highlight.1, c.highlight.2))),
+            Span::styled(format!("{:>6}  ", entry.users), Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2))),
+        ];
+        spans = textstyle::text_style(&Cow::Borrowed(entry.topic.as_str()), spans, txt, app, |_,_| {});
+        ListItem::new(Line::from(spans))
+    }).collect();
+
+    let sort = match list.sort {
+        ListSort::Users => "users",
+        ListSort::Name => "name",
+    };
+    let count = if list.loading {
+        format!("Loading... {}", list.entries.len())
     } else {
-        Paragraph::new(list_lines).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(list_length.to_string()).right_aligned()))
+        format!("{}/{}", list.shown.len(), list.entries.len())
     };
+    let list_block = List::new(list_lines).highlight_symbol("🮥 ").highlight_style(Style::default().add_modifier(Modifier::REVERSED)).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(format!("Channels by {}", sort)).left_aligned()).title(Line::from(count).right_aligned()).title_bottom(Line::from("Enter join, alt + s sort").centered()));
+    let filter_block = Paragraph::new(list.filter.as_str()).style(txt).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from("Filter").left_aligned()));
+    let mut state = ListState::default().with_selected((!list.shown.is_empty()).then_some(list.selected - list.offset));
 
-    let list_popup_area = pop_area(frame.area(), 80, 70);
     frame.render_widget(Clear, list_popup_area); //this clears out the background
-    frame.render_widget(list_block, list_popup_area);
+    frame.render_widget(filter_block, filter_area);
+    frame.render_stateful_widget(list_block, list_area, &mut state);
 }
 
 fn render_help_pop(frame: &mut Frame, c: &Colors) {
@@ -148,27 +196,52 @@ fn render_help_pop(frame: &mut Frame, c: &Colors) {
 }
 
 fn render_user_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
-    let mut user_lines: Vec<Line> = Vec::new();
-    let mut user_length: usize = 0;
-    if let Some(server) = app.server_list.get(&app.active_server) {
-        if let Some(channel) = server.channels.get(&app.active_channel) {
-            user_length = channel.user_list.len();
-            for user in &channel.user_list {
-                user_lines.push(Line::from(Span::from(user).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2)))));
+    let users = userlist::visible_users(app);
+    let counts = match app.server_list.get(&app.active_server) {
+        Some(server) => userlist::counts(&users, &server.mode_table),
+        None => String::new(),
+    };
+    let user_lines: Vec<ListItem> = users.iter()
+        .map(|user| {
+            let mut style = Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2));
+            if user.away {
+                style = style.add_modifier(Modifier::DIM);
             }
-        }
-    }
-
-    let user_block = if user_length > ((app.h as usize * 70) / 100) - 1 {
-        let slice = &user_lines[app.menu_pos..((app.h as usize * 70) / 100) - 1 + app.menu_pos];
-        Paragraph::new(slice.to_vec()).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(user_length.to_string()).right_aligned()).title(Line::from("Users").left_aligned()))
-    } else {
-        Paragraph::new(user_lines).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(user_length.to_string()).right_aligned()).title(Line::from("Users").left_aligned()))
+            ListItem::new(Line::from(Span::from(user.display()).style(style)))
+        })
+        .collect();
+    let bottom = match &app.user_popup.confirm {
+        Some(Confirm::Kick(nick)) => format!("Kick {}? y/Enter to confirm", nick),
+        Some(Confirm::Ban(nick)) => format!("Ban {}? y/Enter to confirm", nick),
+        None => "Enter whois, Alt+q query o op d deop v voice k kick b ban i ignore".to_string(),
     };
 
-    let user_popup_area = pop_area(frame.area(), 30, 60);
+    let user_block = List::new(user_lines).highlight_symbol("🮥 ").highlight_style(Style::default().fg(Color::Rgb(c.highlight.0, c.highlight.1, c.highlight.2))).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(counts).right_aligned()).title(Line::from("Users").left_aligned()).title_bottom(Line::from(bottom).centered()));
+    let filter_block = Paragraph::new(app.user_popup.filter.as_str()).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from("Filter").left_aligned()));
+
+    let user_popup_area = pop_area(frame.area(), 40, 60);
+    let [filter_area, list_area] = Layout::vertical([Length(3), Min(0)]).areas(user_popup_area);
     frame.render_widget(Clear, user_popup_area); //this clears out the background
-    frame.render_widget(user_block, user_popup_area);
+    frame.render_widget(filter_block, filter_area);
+    frame.render_stateful_widget(user_block, list_area, &mut app.user_popup.state);
+}
+
+fn render_whois_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
+    let info = match &app.whois.shown {
+        Some(i) => i,
+        None => return,
+    };
+    let whois_lines: Vec<Line> = info.rows().into_iter().map(|(label, value)| {
+        Line::from(vec![
+            Span::styled(format!("{:<10}", label), Style::default().fg(Color::Rgb(c.highlight.0, c.highlight.1, c.highlight.2)).add_modifier(Modifier::BOLD)),
+            Span::styled(value, Style::default().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))),
+        ])
+    }).collect();
+    let title = if info.whowas { format!("Whowas {}", info.nick) } else { format!("Whois {}", info.nick) };
+    let whois_block = Paragraph::new(whois_lines).wrap(Wrap { trim: true }).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(title).centered()));
+    let whois_popup_area = pop_area(frame.area(), 60, 40);
+    frame.render_widget(Clear, whois_popup_area); //this clears out the background
+    frame.render_widget(whois_block, whois_popup_area);
 }
 
 fn render_chan_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
@@ -177,10 +250,10 @@ fn render_chan_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
     let mut state_index = 0;
 
     for (outer_key, inner_map) in &app.server_list {
-        channel_lines.push(Line::from(outer_key.to_owned()).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))));
+        channel_lines.push(Line::from(inner_map.display_name(outer_key).to_owned()).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))));
         state_index += 1;
-        for (inner_key, data) in &inner_map.channels {
-            if data.notification == true && inner_key != "Status" {
+        for (inner_key, data) in inner_map.channels.iter() {
+            if data.activity != Activity::None && inner_key != "Status" {
                 channel_lines.push(Line::from(vec![Span::from(format!("[!] ")).style(Style::new().fg(Color::Rgb(c.notif.0, c.notif.1, c.notif.2))), Span::from(format!("{}: {}", index, inner_key)).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2)))]));
             } else {
                 channel_lines.push(Line::from(format!("    {}: {}", index, inner_key)).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))));
@@ -201,65 +274,152 @@ fn render_chan_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
     frame.render_stateful_widget(channel_block, channel_popup_area, &mut app.channel_state);
 }
 
-fn render_main(frame: &mut Frame, app: &mut App, colors: &Colors, area: Rect) {
-    let mut lines: Vec<Line> = textstyle::chat_style(app, app.active_server.clone(), app.active_channel.clone());
+fn render_search_pop(frame: &mut Frame, app: &mut App, c: &Colors) {
+    let mut result_lines: Vec<ListItem> = Vec::new();
+    for found in &app.search.results {
+        let stamp = found.time.format("%Y-%m-%d %H:%M").to_string();
+        result_lines.push(ListItem::new(Line::from(vec![
+            Span::from(format!("{} {} ", found.server, found.channel)).style(Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2))),
+            Span::from(format!("[{}] ", stamp)).style(Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2))),
+            Span::from(format!("{}: ", found.nick)).style(Style::new().fg(Color::Rgb(c.highlight.0, c.highlight.1, c.highlight.2)).add_modifier(Modifier::BOLD)),
+            Span::from(found.text.clone()).style(Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2))),
+        ])));
+    }
+    let query = app.search.regex.as_ref().map(|r| r.as_str().to_string()).unwrap_or_default();
+
+    let search_block = List::new(result_lines).highlight_symbol("🮥 ").highlight_style(Style::default().add_modifier(Modifier::REVERSED)).block(Block::bordered().style(Style::default().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).bg(Color::Rgb(c.bg.0 - 10, c.bg.1 - 10, c.bg.2 - 10))).title(Line::from(format!("Search: {}", query)).left_aligned()).title(Line::from(app.search.results.len().to_string()).right_aligned()));
+    let search_popup_area = pop_area(frame.area(), 80, 70);
+    frame.render_widget(Clear, search_popup_area); //this clears out the background
+    frame.render_stateful_widget(search_block, search_popup_area, &mut app.search.state);
+}
+
+// Server/channel tree with the /swap numbers, colored by activity
+fn render_sidebar(frame: &mut Frame, app: &mut App, c: &Colors, area: Rect) {
+    let block = Block::default().borders(Borders::RIGHT).border_style(Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)));
+    let inner = block.inner(area);
+    let width = inner.width as usize;
 
-    if lines.len() > app.h as usize - 6 {
-        if let Some(server) = app.server_list.get(&app.active_server) {
-            if let Some(channel) = server.channels.get(&app.active_channel) {
-                let chat_slice_start = lines.len().saturating_sub(app.h as usize - 6).saturating_sub(channel.chat_pos);
-                lines = lines[chat_slice_start..].to_vec();
+    // (line, buffer number) for every row, servers have no number
+    let mut rows: Vec<(Line, Option<usize>)> = Vec::new();
+    let mut active_row = 0;
+    let mut index = 0;
+    for (server_id, server) in &app.server_list {
+        let name = server.display_name(server_id);
+        rows.push((Line::from(Span::styled(truncate(name, width), Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2)).add_modifier(Modifier::BOLD))), None));
+        for (channel_id, channel) in server.channels.iter() {
+            let mut style = match channel.activity {
+                Activity::None => Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)).add_modifier(Modifier::DIM),
+                Activity::Events => Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2)),
+                Activity::Messages => Style::new().fg(Color::Rgb(c.notif.0, c.notif.1, c.notif.2)),
+                Activity::Highlight => Style::new().fg(Color::Rgb(c.highlight.0, c.highlight.1, c.highlight.2)).add_modifier(Modifier::BOLD),
+            };
+            let active = *server_id == app.active_server && *channel_id == app.active_channel;
+            if active {
+                style = Style::new().fg(Color::Rgb(c.txt.0, c.txt.1, c.txt.2)).add_modifier(Modifier::REVERSED);
+                active_row = rows.len();
             }
+            let count = if channel.unread > 0 { format!(" {}", channel.unread) } else { String::new() };
+            let label = truncate(&format!(" {} {}", index, channel_id), width.saturating_sub(count.width()));
+            let padding = " ".repeat(width.saturating_sub(label.width() + count.width()));
+            rows.push((Line::from(Span::styled(format!("{}{}{}", label, padding, count), style)), Some(index)));
+            index += 1;
         }
     }
 
-    let message_layout = List::new(lines).block(Block::bordered().border_style(Style::new().fg(Color::Rgb(colors.fg. 0,colors.fg.1, colors.fg.2))));
-    frame.render_widget(message_layout, area);
+    // Scrolled so the active buffer stays in view
+    let height = inner.height as usize;
+    let offset = (active_row + 1).saturating_sub(height);
+    let (lines, numbers): (Vec<Line>, Vec<Option<usize>>) = rows.into_iter().skip(offset).take(height).unzip();
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+    app.sidebar_area = inner;
+    app.sidebar_rows = numbers;
 }
 
-fn render_split_main(frame: &mut Frame, app: &mut App, colors: &Colors, area: Rect) {
-    let (_, server_left, left, server_right, right) = app.split.clone();
-    let split_chat = Layout::horizontal([Fill(1), Fill(1)]);
-    let [split_left, split_right] = split_chat.areas(area);
-
-    let mut lines_left: Vec<Line> = textstyle::chat_style(app, server_left.clone(), left.clone());
+// Users of the active channel by rank then nick, away users dimmed
+fn render_nicklist(frame: &mut Frame, app: &App, c: &Colors, area: Rect) {
+    let users = app.users_by_rank(&app.active_server, &app.active_channel);
+    let block = Block::default().borders(Borders::LEFT).border_style(Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2)))
+        .title(Line::from(format!(" {} ", users.len())).right_aligned());
+    let width = block.inner(area).width as usize;
+    let lines: Vec<Line> = users.iter().map(|user| {
+        let symbol = user.highest().map(String::from).unwrap_or(" ".to_string());
+        let mut style = Style::new().fg(textstyle::nick_color(&user.nick));
+        if user.away {
+            style = style.add_modifier(Modifier::DIM);
+        }
+        Line::from(vec![
+            Span::styled(symbol, Style::new().fg(Color::Rgb(c.fg.0, c.fg.1, c.fg.2))),
+            Span::styled(truncate(&user.nick, width.saturating_sub(1)), style),
+        ])
+    }).collect();
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
 
-    if lines_left.len() > app.h as usize - 6 {
-        if let Some(server) = app.server_list.get(&server_left) {
-            if let Some(channel) = server.channels.get(&left) {
-                let chat_slice_start = lines_left.len().saturating_sub(app.h as usize - 6).saturating_sub(channel.chat_pos);
-                lines_left = lines_left[chat_slice_start..].to_vec();
-            }
+// Cuts a name to a width in columns
+fn truncate(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    for ch in text.chars() {
+        if out.width() + UnicodeWidthStr::width(ch.to_string().as_str()) > width {
+            break;
         }
+        out.push(ch);
     }
+    out
+}
 
-    if app.active_server == server_left && app.active_channel == left {
-        let message_layout_left = List::new(lines_left).block(Block::bordered().title_top(left).border_style(Style::new().fg(Color::Rgb(colors.highlight. 0,colors.highlight.1, colors.highlight.2))));
-        frame.render_widget(message_layout_left, split_left);
-    } else {
-        let message_layout_left = List::new(lines_left).block(Block::bordered().title_top(left).border_style(Style::new().fg(Color::Rgb(colors.fg. 0,colors.fg.1, colors.fg.2))));
-        frame.render_widget(message_layout_left, split_left);
-    }
+fn has_topic(app: &App, server_id: &str, channel_id: &str) -> bool {
+    app.server_list.get(server_id).and_then(|s| s.channels.get(channel_id)).is_some_and(|c| c.topic.is_some())
+}
 
+// One line with the topic formatting, setter and time after it
+fn render_topic(frame: &mut Frame, app: &App, colors: &Colors, area: Rect, server_id: &str, channel_id: &str) {
+    let topic = match app.server_list.get(server_id).and_then(|s| s.channels.get(channel_id)).and_then(|c| c.topic.as_ref()) {
+        Some(t) => t,
+        None => return,
+    };
+    let style = Style::new().fg(Color::Rgb(colors.txt.0, colors.txt.1, colors.txt.2));
+    let mut spans = vec![Span::raw(" ")];
+    spans = textstyle::text_style(&Cow::Borrowed(topic.text.as_str()), spans, style, app, |_,_| {});
+    let set_by = match (&topic.setter, topic.time) {
+        (Some(setter), Some(time)) => format!("  ({}, {})", setter, time.format("%Y-%m-%d %H:%M")),
+        (Some(setter), None) => format!("  ({})", setter),
+        _ => String::new(),
+    };
+    spans.push(Span::styled(set_by, Style::new().fg(Color::Rgb(colors.fg.0, colors.fg.1, colors.fg.2)).add_modifier(Modifier::DIM)));
+    let bar = Paragraph::new(Line::from(spans)).style(Style::default().bg(Color::Rgb(colors.bg.0.saturating_sub(10), colors.bg.1.saturating_sub(10), colors.bg.2.saturating_sub(10))));
+    frame.render_widget(bar, area);
+}
+
+// Every pane of the window layout with its own topic bar, the focused one framed when there are several
+fn render_panes(frame: &mut Frame, app: &mut App, colors: &Colors) {
+    let split = app.layout.is_split();
+    let panes: Vec<(Pane, Rect)> = app.layout.panes().into_iter().cloned().zip(app.layout.rects.iter().copied()).collect();
+    for (index, (pane, area)) in panes.into_iter().enumerate() {
+        let focused = split && index == app.layout.focus;
+        let topic_rows = if has_topic(app, &pane.server, &pane.channel) { 1 } else { 0 };
+        let [topic_area, chat_area] = Layout::vertical([Length(topic_rows), Min(0)]).areas(area);
+        render_topic(frame, app, colors, topic_area, &pane.server, &pane.channel);
+        render_pane(frame, app, colors, chat_area, &pane.server, &pane.channel, focused);
+    }
+}
 
-    let mut lines_right: Vec<Line> = textstyle::chat_style(app, server_right.clone(), right.clone());
+fn render_pane(frame: &mut Frame, app: &App, colors: &Colors, area: Rect, server_id: &str, channel_id: &str, focused: bool) {
+    let mut lines: Vec<Line> = textstyle::chat_style(app, server_id.to_string(), channel_id.to_string());
+    let rows = area.height.saturating_sub(2) as usize;
 
-    if lines_right.len() > app.h as usize - 6 {
-        if let Some(server) = app.server_list.get(&server_right) {
-            if let Some(channel) = server.channels.get(&right) {
-                let chat_slice_start = lines_right.len().saturating_sub(app.h as usize - 6).saturating_sub(channel.chat_pos);
-                lines_right = lines_right[chat_slice_start..].to_vec();
+    if lines.len() > rows {
+        if let Some(server) = app.server_list.get(server_id) {
+            if let Some(channel) = server.channels.get(channel_id) {
+                let chat_slice_start = lines.len().saturating_sub(rows).saturating_sub(channel.chat_pos);
+                lines = lines[chat_slice_start..].to_vec();
             }
         }
     }
 
-    if app.active_server == server_right && app.active_channel == right{
-        let message_layout_right = List::new(lines_right).block(Block::bordered().title_top(right).title_alignment(Alignment::Right).border_style(Style::new().fg(Color::Rgb(colors.highlight. 0,colors.highlight.1, colors.highlight.2))));
-        frame.render_widget(message_layout_right, split_right);
-    } else {
-        let message_layout_right = List::new(lines_right).block(Block::bordered().title_top(right).title_alignment(Alignment::Right).border_style(Style::new().fg(Color::Rgb(colors.fg. 0,colors.fg.1, colors.fg.2))));
-        frame.render_widget(message_layout_right, split_right);
-    }
+    let title = app.buffer_title(server_id, channel_id);
+    let border = if focused { colors.highlight } else { colors.fg };
+    let message_layout = List::new(lines).block(Block::bordered().title_top(title).border_style(Style::new().fg(Color::Rgb(border.0, border.1, border.2))));
+    frame.render_widget(message_layout, area);
 }
 
 fn get_colors(app: &App) -> Colors {
@@ -345,30 +505,21 @@ fn color_lines() -> Vec<ListItem<'static>> {
 }
 
 fn help_lines() -> Vec<Line<'static>> {
-    vec![
-        Line::from(vec![
-            Span::styled("/quit                    ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To quit the application", Style::default()),
-        ]),
-        Line::from(vec![
-            Span::styled("/connect ip              ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To connect to a server", Style::default()),
-        ]),
-        Line::from(vec![
-            Span::styled("/disconnect server       ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To disconnect from a server", Style::default()),
-        ]),
-        Line::from(vec![
-            Span::styled("/Join #Channel           ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To join a chat channel", Style::default()),
-        ]),
+    // Commands come from the registry, key bindings after them
+    let mut lines: Vec<Line<'static>> = command::COMMANDS.iter().map(|c| {
+        let mut help = c.help.to_string();
+        if !c.aliases.is_empty() {
+            help += &format!(" (also /{})", c.aliases.join(", /"));
+        }
         Line::from(vec![
-            Span::styled("/part #channel           ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To leave a chat channel", Style::default()),
-        ]),
+            Span::styled(format!("{:<25}", format!("/{} {}", c.name, c.args)), Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(format!(": {}", help), Style::default()),
+        ])
+    }).collect();
+    lines.extend(vec![
         Line::from(vec![
             Span::styled("/'command'               ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To use a command", Style::default()),
+            Span::styled(": Any other command is sent to the server as it is", Style::default()),
         ]),
         Line::from(vec![
             Span::styled("message styling Bold     ", Style::default().add_modifier(Modifier::BOLD)),
@@ -399,33 +550,45 @@ fn help_lines() -> Vec<Line<'static>> {
             Span::styled(": Cycle channel chat history", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("/list                    ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To list channels, Esc to close window", Style::default()),
+            Span::styled("ctrl + 'f'               ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Search active channel as you type, Enter or ctrl + 'f' for older, Esc to stop", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("/alis (Libera.Chat)      ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": To list channels, Esc to close window", Style::default()),
+            Span::styled("Tab                      ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Complete nick, #channel or /command, again for the next match", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("/twitch_connect          ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": Join twitch, use ouath file", Style::default()),
+            Span::styled("Shift + Tab              ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Focus the previous pane, Tab with an empty prompt the next one", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("/swap 'number'           ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": Swap active channel", Style::default()),
+            Span::styled("ctrl + arrows            ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Resize the focused pane", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("/split 'number'-'number' ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": Split screen view", Style::default()),
+            Span::styled("alt + 'i'                ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Join the channel of the last invite", Style::default()),
         ]),
         Line::from(vec![
-            Span::styled("Tab                      ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::styled(": Switch active channel in split view", Style::default()),
+            Span::styled("F4                       ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Show or hide the sidebar, click a buffer in it to switch", Style::default()),
+        ]),
+        Line::from(vec![
+            Span::styled("F5                       ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Show or hide the nicklist of the active channel", Style::default()),
+        ]),
+        Line::from(vec![
+            Span::styled("alt + number             ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Switch to the buffer with that number, like /swap", Style::default()),
+        ]),
+        Line::from(vec![
+            Span::styled("F2 type / alt + key      ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(": Filter the user list, alt + q query, o op, d deop, v voice, k kick, b ban, i ignore", Style::default()),
         ]),
         Line::from(vec![
             Span::styled("Esc                      ", Style::default().add_modifier(Modifier::BOLD)),
             Span::styled(": Close Popup", Style::default()),
         ]),
-
-    ]
+    ]);
+    lines
 }
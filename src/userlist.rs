@@ -0,0 +1,151 @@
+// userlist.rs
+use ratatui::widgets::ListState;
+use crate::app::{App, Popup};
+use crate::mode::{User, ModeTable};
+use crate::message::IrcMessage;
+
+// Kick and ban wait for a second key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Confirm {
+    Kick(String),
+    Ban(String),
+}
+
+// State of the F2 user popup
+#[derive(Default)]
+pub struct UserPopup {
+    pub state: ListState,
+    // Typed while the popup is open, matches anywhere in the nick
+    pub filter: String,
+    pub confirm: Option<Confirm>,
+}
+
+pub fn open(app: &mut App) {
+    app.user_popup = UserPopup::default();
+    app.user_popup.state.select(Some(0));
+    app.popup = Popup::User;
+    app.active_tab = 1;
+}
+
+pub fn close(app: &mut App) {
+    app.user_popup.confirm = None;
+    app.popup = Popup::None;
+    app.active_tab = usize::MAX;
+}
+
+// Users of the active channel matching the filter, by rank then nick
+pub fn visible_users(app: &App) -> Vec<&User> {
+    let filter = app.user_popup.filter.to_lowercase();
+    app.users_by_rank(&app.active_server, &app.active_channel).into_iter()
+        .filter(|u| u.nick.to_lowercase().contains(&filter))
+        .collect()
+}
+
+pub fn selected(app: &App) -> Option<User> {
+    let users = visible_users(app);
+    app.user_popup.state.selected().and_then(|i| users.get(i)).map(|u| (*u).clone())
+}
+
+pub fn move_selection(app: &mut App, step: isize) {
+    let last = visible_users(app).len().saturating_sub(1);
+    let current = app.user_popup.state.selected().unwrap_or(0);
+    app.user_popup.state.select(Some(current.saturating_add_signed(step).min(last)));
+}
+
+// A typed character, answers a confirmation or goes to the filter
+pub fn type_char(app: &mut App, c: char) {
+    if let Some(confirm) = app.user_popup.confirm.take() {
+        if c.eq_ignore_ascii_case(&'y') {
+            run_confirmed(app, confirm);
+        }
+        return;
+    }
+    app.user_popup.filter.push(c);
+    app.user_popup.state.select(Some(0));
+}
+
+pub fn backspace(app: &mut App) {
+    app.user_popup.confirm = None;
+    app.user_popup.filter.pop();
+    app.user_popup.state.select(Some(0));
+}
+
+// Enter confirms a kick or ban, otherwise shows the whois of the selected nick
+pub fn enter(app: &mut App) {
+    if let Some(confirm) = app.user_popup.confirm.take() {
+        run_confirmed(app, confirm);
+        return;
+    }
+    if let Some(user) = selected(app) {
+        // The whois popup opens when the replies are in
+        app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("WHOIS", &[&user.nick]).to_string());
+    }
+    close(app);
+}
+
+// Alt + key on the selected nick
+pub fn action(app: &mut App, key: char) {
+    let user = match selected(app) {
+        Some(u) => u,
+        None => return,
+    };
+    let chan = app.active_channel.clone();
+    match key {
+        'q' => {
+            app.open_query(&app.active_server.clone(), &user.nick);
+            close(app);
+        }
+        'o' => set_prefix(app, &chan, &user, '@', true),
+        'd' => set_prefix(app, &chan, &user, '@', false),
+        'v' => set_prefix(app, &chan, &user, '+', !user.prefixes.contains('+')),
+        'k' => app.user_popup.confirm = Some(Confirm::Kick(user.nick)),
+        'b' => app.user_popup.confirm = Some(Confirm::Ban(user.nick)),
+        'i' => {
+            app.ignore(&user.nick);
+            close(app);
+        }
+        _ => {}
+    }
+}
+
+// MODE +o/-o or +v/-v, by the mode letter the server uses for the symbol
+fn set_prefix(app: &mut App, chan: &str, user: &User, symbol: char, set: bool) {
+    let mode = app.server_list.get(&app.active_server)
+        .and_then(|s| s.mode_table.prefix.iter().find(|(_, p)| *p == symbol).map(|(m, _)| *m));
+    if let Some(mode) = mode {
+        let change = format!("{}{}", if set { '+' } else { '-' }, mode);
+        app.stream_mgr.send_line(app.active_server.clone(), IrcMessage::new("MODE", &[chan, &change, &user.nick]).to_string());
+    }
+}
+
+fn run_confirmed(app: &mut App, confirm: Confirm) {
+    let chan = app.active_channel.clone();
+    let server_id = app.active_server.clone();
+    match confirm {
+        Confirm::Kick(nick) => {
+            app.stream_mgr.send_line(server_id, IrcMessage::new("KICK", &[&chan, &nick]).to_string());
+        }
+        Confirm::Ban(nick) => {
+            let mask = format!("{}!*@*", nick);
+            app.stream_mgr.send_line(server_id.clone(), IrcMessage::new("MODE", &[&chan, "+b", &mask]).to_string());
+            app.stream_mgr.send_line(server_id, IrcMessage::new("KICK", &[&chan, &nick]).to_string());
+        }
+    }
+}
+
+// "42 users, 3 ops, 5 voiced", halfops only when there are any
+pub fn counts(users: &[&User], table: &ModeTable) -> String {
+    let rank_of = |symbol: char| table.prefix.iter().position(|(_, s)| *s == symbol);
+    // Everything from @ up counts as an op
+    let ops = match rank_of('@') {
+        Some(op_rank) => users.iter().filter(|u| table.rank(u) <= op_rank).count(),
+        None => 0,
+    };
+    let halfops = users.iter().filter(|u| rank_of('%').is_some_and(|r| table.rank(u) == r)).count();
+    let voiced = users.iter().filter(|u| rank_of('+').is_some_and(|r| table.rank(u) == r)).count();
+    let mut text = format!("{} users, {} ops", users.len(), ops);
+    if halfops > 0 {
+        text += &format!(", {} halfops", halfops);
+    }
+    text + &format!(", {} voiced", voiced)
+}
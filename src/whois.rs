@@ -0,0 +1,200 @@
+// whois.rs
+use std::collections::HashMap;
+use chrono::{DateTime, Local};
+use crate::casemap::CaseMapping;
+use crate::message::IrcMessage;
+
+// Everything the server said about one nick between the first reply and 318/369
+#[derive(Debug, Clone, Default)]
+pub struct WhoisInfo {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    pub server: Option<String>,
+    pub server_info: Option<String>,
+    pub account: Option<String>,
+    pub idle: Option<u64>,
+    pub signon: Option<DateTime<Local>>,
+    pub channels: Vec<String>,
+    pub away: Option<String>,
+    // From WHOWAS, the nick is no longer online
+    pub whowas: bool,
+}
+
+#[derive(Default)]
+pub struct Whois {
+    // (server, normalized nick) -> replies so far
+    pending: HashMap<(String, String), WhoisInfo>,
+    // Shown in Popup::Whois
+    pub shown: Option<WhoisInfo>,
+}
+
+impl Whois {
+    fn entry(&mut self, server_id: &str, casemap: CaseMapping, nick: &str) -> &mut WhoisInfo {
+        self.pending.entry((server_id.to_string(), casemap.normalize(nick)))
+            .or_insert_with(|| WhoisInfo { nick: nick.to_string(), ..Default::default() })
+    }
+
+    // 311 312 314 317 319 330, all start with our own nick and the nick asked about
+    pub fn reply(&mut self, server_id: &str, casemap: CaseMapping, msg: &IrcMessage) {
+        let info = self.entry(server_id, casemap, msg.param(1));
+        match msg.command.as_str() {
+            "311" | "314" => {
+                info.user = Some(msg.param(2).to_string());
+                info.host = Some(msg.param(3).to_string());
+                info.realname = Some(msg.param(5).to_string());
+                info.whowas = msg.command == "314";
+            }
+            "312" => {
+                info.server = Some(msg.param(2).to_string());
+                info.server_info = Some(msg.param(3).to_string());
+            }
+            "317" => {
+                info.idle = msg.param(2).parse().ok();
+                info.signon = msg.param(3).parse::<i64>().ok().and_then(|t| DateTime::from_timestamp(t, 0)).map(|t| t.with_timezone(&Local));
+            }
+            "319" => info.channels.extend(msg.param(2).split_whitespace().map(|c| c.to_string())),
+            "330" => info.account = Some(msg.param(2).to_string()),
+            _ => {}
+        }
+    }
+
+    // 301 while a whois is running belongs to it, false when it does not
+    pub fn away(&mut self, server_id: &str, casemap: CaseMapping, nick: &str, message: &str) -> bool {
+        match self.pending.get_mut(&(server_id.to_string(), casemap.normalize(nick))) {
+            Some(info) => {
+                info.away = Some(message.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 318 or 369, true when there is something to show
+    pub fn finish(&mut self, server_id: &str, casemap: CaseMapping, nick: &str) -> bool {
+        match self.pending.remove(&(server_id.to_string(), casemap.normalize(nick))) {
+            Some(info) => {
+                self.shown = Some(info);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl WhoisInfo {
+    // (label, value) rows for the popup, missing fields are left out
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows = vec![("Nick", self.nick.clone())];
+        if let (Some(user), Some(host)) = (&self.user, &self.host) {
+            rows.push(("Host", format!("{}@{}", user, host)));
+        }
+        if let Some(realname) = &self.realname {
+            rows.push(("Realname", realname.clone()));
+        }
+        if let Some(server) = &self.server {
+            let info = self.server_info.as_deref().unwrap_or("");
+            rows.push(("Server", if info.is_empty() { server.clone() } else { format!("{} ({})", server, info) }));
+        }
+        if let Some(account) = &self.account {
+            rows.push(("Account", account.clone()));
+        }
+        if let Some(idle) = self.idle {
+            rows.push(("Idle", human_duration(idle)));
+        }
+        if let Some(signon) = self.signon {
+            let online = (Local::now() - signon).num_seconds().max(0) as u64;
+            rows.push(("Signed on", format!("{} ({} ago)", signon.format("%Y-%m-%d %H:%M"), human_duration(online))));
+        }
+        if !self.channels.is_empty() {
+            rows.push(("Channels", self.channels.join(" ")));
+        }
+        if let Some(away) = &self.away {
+            rows.push(("Away", away.clone()));
+        }
+        rows
+    }
+}
+
+// "2d 3h", "4h 12m", "5m 10s", "42s"
+pub fn human_duration(secs: u64) -> String {
+    let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(whois: &mut Whois, line: &str) {
+        whois.reply("srv", CaseMapping::Rfc1459, &IrcMessage::parse(line).unwrap());
+    }
+
+    #[test]
+    fn collects_until_end() {
+        let mut whois = Whois::default();
+        reply(&mut whois, ":irc.test 311 me Bob bob host.example * :Bob Smith");
+        reply(&mut whois, ":irc.test 312 me bob irc.test :Test server");
+        reply(&mut whois, ":irc.test 319 me bob :@#a +#b");
+        reply(&mut whois, ":irc.test 319 me bob :#c");
+        reply(&mut whois, ":irc.test 330 me bob bobacct :is logged in as");
+        reply(&mut whois, ":irc.test 317 me bob 3725 1700000000 :seconds idle, signon time");
+        assert!(whois.away("srv", CaseMapping::Rfc1459, "BOB", "gone fishing"));
+        assert!(whois.shown.is_none());
+
+        assert!(whois.finish("srv", CaseMapping::Rfc1459, "bob"));
+        let info = whois.shown.clone().unwrap();
+        assert_eq!(info.nick, "Bob");
+        assert_eq!(info.channels, vec!["@#a", "+#b", "#c"]);
+        assert_eq!(info.away.as_deref(), Some("gone fishing"));
+        assert!(!info.whowas);
+
+        let rows = info.rows();
+        assert_eq!(rows[1], ("Host", "bob@host.example".to_string()));
+        assert!(rows.contains(&("Server", "irc.test (Test server)".to_string())));
+        assert!(rows.contains(&("Account", "bobacct".to_string())));
+        assert!(rows.contains(&("Idle", "1h 2m".to_string())));
+
+        // Done with bob, a second end has nothing to show
+        assert!(!whois.finish("srv", CaseMapping::Rfc1459, "bob"));
+    }
+
+    #[test]
+    fn away_outside_whois() {
+        let mut whois = Whois::default();
+        assert!(!whois.away("srv", CaseMapping::Rfc1459, "bob", "gone fishing"));
+        // Running on another server does not count either
+        reply(&mut whois, ":irc.test 311 me bob bob host.example * :Bob");
+        assert!(!whois.away("other", CaseMapping::Rfc1459, "bob", "gone fishing"));
+        assert!(whois.finish("srv", CaseMapping::Rfc1459, "bob"));
+        assert!(whois.shown.as_ref().unwrap().away.is_none());
+    }
+
+    #[test]
+    fn whowas_ends_with_369() {
+        let mut whois = Whois::default();
+        reply(&mut whois, ":irc.test 314 me bob bob old.host * :Bob");
+        reply(&mut whois, ":irc.test 312 me bob irc.test :Mon Oct 16 2026");
+        assert!(whois.finish("srv", CaseMapping::Rfc1459, "bob"));
+        let info = whois.shown.unwrap();
+        assert!(info.whowas);
+        assert_eq!(info.host.as_deref(), Some("old.host"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(human_duration(42), "42s");
+        assert_eq!(human_duration(310), "5m 10s");
+        assert_eq!(human_duration(15120), "4h 12m");
+        assert_eq!(human_duration(183600), "2d 3h");
+    }
+}